@@ -3,7 +3,7 @@ use std::fs;
 use std::path::PathBuf;
 
 // The memory.x file for the RA4M2 needs to be linked. This script copies the existing 
-// memory.x file to the OUT_DIR and lets the linker know where to find it. 
+// memory.x file (plus the QSPI flash region, if enabled) to the OUT_DIR and lets the linker know where to find it. 
 // Embassy generates this file automatically, while we are just copying ours for now.
 // https://github.com/embassy-rs/embassy/blob/main/embassy-stm32/build.rs
 
@@ -14,11 +14,25 @@ fn main() {
     let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("memory.x");
     let dest = out.join("memory.x");
 
-    fs::copy(&source, &dest).expect("Could not copy memory.x");
+    let mut memory = fs::read_to_string(&source).expect("Could not read memory.x");
+
+    // With the qspi feature, map the external flash's ROM access area so code
+    // and data can be placed there with #[link_section = ".qspi_flash"]
+    if env::var_os("CARGO_FEATURE_QSPI").is_some() {
+        let size = env::var("RA4M2_QSPI_FLASH_SIZE").unwrap_or_else(|_| "16M".to_string());
+        memory.push_str(&format!(
+            "\n\nMEMORY\n{{\n  QSPI_FLASH : ORIGIN = 0x60000000, LENGTH = {size}\n}}\n\n\
+             SECTIONS\n{{\n  .qspi_flash : ALIGN(4)\n  {{\n    *(.qspi_flash .qspi_flash.*);\n    . = ALIGN(4);\n  }} > QSPI_FLASH\n}}\n\
+             INSERT AFTER .rodata;\n"
+        ));
+    }
+
+    fs::write(&dest, memory).expect("Could not write memory.x");
 
     // Tell the linker to look in this OUT_DIR — this *will* propagate
     println!("cargo:rustc-link-search={}", out.display());
 
     // Rebuild if memory.x changes
     println!("cargo:rerun-if-changed={}", source.display());
+    println!("cargo:rerun-if-env-changed=RA4M2_QSPI_FLASH_SIZE");
 }
\ No newline at end of file
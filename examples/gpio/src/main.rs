@@ -31,6 +31,7 @@ fn main() -> ! {
     let config = ra4m2_hal::sysc::SystemClockConfig {
         system_clock_divider: ra4m2_hal::sysc::SystemClockDividerConfig::default(),
         external_oscillator: 24_000_000, // 24 MHz
+        hoco_frequency: ra4m2_hal::sysc::HocoFrequency::Mhz20,
         clock_source: ra4m2_hal::sysc::ClockSource::MainClockOsc,
     };
 
@@ -5,7 +5,7 @@ use core::ptr::addr_of_mut;
 
 use cortex_m_rt::entry;
 use log::info;
-use ra4m2_hal::i2c::I2c0;
+use ra4m2_hal::i2c::{I2c0, I2cConfig, Iic0};
 use embedded_time::Clock;
 use embedded_hal::digital::OutputPin;
 use embedded_hal::i2c::{I2c, Operation};
@@ -35,6 +35,7 @@ fn main() -> ! {
     let config = ra4m2_hal::sysc::SystemClockConfig {
         system_clock_divider,
         external_oscillator: 24_000_000, // 24 MHz
+        hoco_frequency: ra4m2_hal::sysc::HocoFrequency::Mhz20,
         clock_source: ra4m2_hal::sysc::ClockSource::MainClockOsc,
     };
 
@@ -55,8 +56,8 @@ fn main() -> ! {
     
     let p4 = ra4m2_hal::gpio::port4::Port4::new(p.PORT4).split();
 
-    let _p4_i2c_sda = p4.p00.into_alternate_function(ra4m2_hal::gpio::PinFunction::IIC);
-    let _p4_i2c_scl = p4.p01.into_alternate_function(ra4m2_hal::gpio::PinFunction::IIC);
+    let mut p4_i2c_scl = p4.p00.into_alternate_function(ra4m2_hal::gpio::PinFunction::IIC);
+    let mut p4_i2c_sda = p4.p01.into_alternate_function(ra4m2_hal::gpio::PinFunction::IIC);
 
     let mut p4_15_blue_led = p4.p15.into_output_push_pull(ra4m2_hal::gpio::DriveMode::Middle);
     let mut p4_04_green_led = p4.p04.into_output_push_pull(ra4m2_hal::gpio::DriveMode::Middle);
@@ -66,7 +67,7 @@ fn main() -> ! {
     info!("The time is now {:?}us", RenesasClock::default().try_now().unwrap().duration_since_epoch().integer());
 
     // This demo talks to an mpu-6050 accelerometer/gyro sensor over I2C.
-    let mut i2c0 = I2c0::new(p.IIC0);
+    let mut i2c0 = I2c0::new(Iic0::new(p.IIC0), &mut p4_i2c_sda, &mut p4_i2c_scl, I2cConfig::default());
 
     let mut buffer = [0u8; 14]; // Fetch 14 bytes for accelerometer, temp, and gyro data
 
@@ -0,0 +1,231 @@
+//! DTC-driven async SPI0 master.
+//!
+//! Each transfer is set up as two DTC transfers: SPTI moves bytes from memory
+//! into SPDR and SPRI moves them from SPDR back out, one per event, so the
+//! CPU is only interrupted once the last byte has been received. Reads clock
+//! out 0xFF from a fixed source, and writes drop received bytes into a fixed
+//! sink.
+//!
+//! The application routes the three SPI events to IELSR slots of its choice
+//! and forwards those interrupts to the `on_*` handlers, as for
+//! `async_uart::AsyncUart`.
+
+use core::{future::poll_fn, sync::atomic::{AtomicBool, AtomicU8, Ordering}, task::Poll};
+
+use cortex_m::interrupt::InterruptNumber;
+use ra4m2_pac::{spi0::{spcr::{Spe, Speie, Sprie, Sptie}, spdcr::Spbyt, spsr::{Modf, Ovrf}}, Interrupt};
+
+use crate::{dtc::{Dtc, TransferInfo}, icu, spi::{Spi0, SpiError, SpiInterrupts, SPDR_ADDRESS, SPEI_EVENT, SPRI_EVENT, SPTI_EVENT}, waker::WakerSlot};
+
+static TX_TRANSFER: TransferInfo = TransferInfo::new();
+static RX_TRANSFER: TransferInfo = TransferInfo::new();
+static WAKER: WakerSlot = WakerSlot::new();
+static DONE: AtomicBool = AtomicBool::new(false);
+
+// Latched by `on_spei`
+const ERROR_OVERRUN: u8 = 1 << 0;
+const ERROR_MODE_FAULT: u8 = 1 << 1;
+static ERRORS: AtomicU8 = AtomicU8::new(0);
+
+/// Transmitted while only reading.
+static FILL: u8 = 0xFF;
+/// Receives bytes while only writing.
+static SINK: AtomicU8 = AtomicU8::new(0);
+
+/// Most bytes one DTC normal-mode transfer can move.
+const MAX_SEGMENT: usize = 0xFFFF;
+
+/// Async SPI0 master on top of `Spi0`, with 8-bit frames.
+pub struct AsyncSpi0<'d> {
+    spi: Spi0<'d>,
+    dtc: &'d mut Dtc,
+    interrupts: SpiInterrupts,
+}
+
+impl<'d> AsyncSpi0<'d> {
+    /// Takes over `spi`, keeping its mode and bit rate.
+    pub fn new(mut spi: Spi0<'d>, dtc: &'d mut Dtc, interrupts: SpiInterrupts) -> Self {
+        spi.use_bus();
+        spi.set_frame(8, 1);
+        unsafe {
+            // The DTC moves single bytes, so SPDR is accessed bytewise
+            spi.regs().spcr().modify(|w| w.spe().set(Spe::_0));
+            spi.regs().spdcr().modify(|w| w.spbyt().set(Spbyt::_1));
+        }
+
+        icu::register_interrupt(interrupts.spri, SPRI_EVENT);
+        icu::register_interrupt(interrupts.spti, SPTI_EVENT);
+        icu::register_interrupt(interrupts.spei, SPEI_EVENT);
+        dtc.attach(interrupts.spri.number(), &RX_TRANSFER);
+        dtc.attach(interrupts.spti.number(), &TX_TRANSFER);
+
+        AsyncSpi0 { spi, dtc, interrupts }
+    }
+
+    /// Hands back the blocking driver.
+    pub fn free(mut self) -> Spi0<'d> {
+        self.stop();
+        self.dtc.detach(self.interrupts.spri.number());
+        self.dtc.detach(self.interrupts.spti.number());
+        unsafe {
+            self.spi.regs().spdcr().modify(|w| w.spbyt().set(Spbyt::_0));
+            self.spi.regs().spcr().modify(|w| w.spe().set(Spe::_1));
+        }
+        self.spi
+    }
+
+    /// SPRI handler: the DTC has stored the last received byte.
+    pub fn on_spri(interrupt: Interrupt) {
+        unsafe {
+            ra4m2_pac::SPI0.spcr().modify(|w| w.sprie().set(Sprie::_0));
+        }
+        icu::clear_interrupt(interrupt);
+        DONE.store(true, Ordering::Release);
+        WAKER.wake();
+    }
+
+    /// SPTI handler: the DTC has loaded the last byte to send.
+    pub fn on_spti(interrupt: Interrupt) {
+        unsafe {
+            ra4m2_pac::SPI0.spcr().modify(|w| w.sptie().set(Sptie::_0));
+        }
+        icu::clear_interrupt(interrupt);
+    }
+
+    /// SPEI handler: records an overrun or mode fault and stops the transfer.
+    pub fn on_spei(interrupt: Interrupt) {
+        unsafe {
+            let spi = ra4m2_pac::SPI0;
+            let spsr = spi.spsr().read();
+            let mut errors = 0;
+            if spsr.ovrf().get().0 == 1 {
+                errors |= ERROR_OVERRUN;
+            }
+            if spsr.modf().get().0 == 1 {
+                errors |= ERROR_MODE_FAULT;
+            }
+            ERRORS.fetch_or(errors, Ordering::Relaxed);
+            spi.spsr().modify(|w| w.ovrf().set(Ovrf::_0).modf().set(Modf::_0));
+            spi.spcr().modify(|w| {
+                w.spe().set(Spe::_0).sprie().set(Sprie::_0).sptie().set(Sptie::_0).speie().set(Speie::_0)
+            });
+        }
+        icu::clear_interrupt(interrupt);
+        WAKER.wake();
+    }
+
+    /// Disables the peripheral and takes both events away from the DTC.
+    fn stop(&mut self) {
+        unsafe {
+            self.spi.regs().spcr().modify(|w| {
+                w.spe().set(Spe::_0).sprie().set(Sprie::_0).sptie().set(Sptie::_0).speie().set(Speie::_0)
+            });
+        }
+        // Re-registering without DTCE stops further transfers
+        icu::register_interrupt(self.interrupts.spri, SPRI_EVENT);
+        icu::register_interrupt(self.interrupts.spti, SPTI_EVENT);
+    }
+
+    /// Moves `len` bytes each way. A non-incrementing side reads or writes
+    /// the same byte every time.
+    async fn segment(&mut self, tx: *const u8, tx_increment: bool, rx: *mut u8, rx_increment: bool, len: usize) -> Result<(), SpiError> {
+        // Stops the DTC from touching the buffers even if this future is
+        // dropped early
+        struct Guard<'a, 'd>(&'a mut AsyncSpi0<'d>);
+        impl Drop for Guard<'_, '_> {
+            fn drop(&mut self) {
+                self.0.stop();
+            }
+        }
+
+        DONE.store(false, Ordering::Relaxed);
+        ERRORS.store(0, Ordering::Relaxed);
+        RX_TRANSFER.normal_from_register(SPDR_ADDRESS, rx, rx_increment, len);
+        TX_TRANSFER.normal_to_register(tx, tx_increment, len, SPDR_ADDRESS);
+        icu::enable_dtc(self.interrupts.spri);
+        icu::enable_dtc(self.interrupts.spti);
+
+        let guard = Guard(self);
+        unsafe {
+            let regs = guard.0.spi.regs();
+            regs.spsr().modify(|w| w.ovrf().set(Ovrf::_0).modf().set(Modf::_0));
+            // Setting SPTIE together with SPE raises the first SPTI
+            regs.spcr().modify(|w| {
+                w.sprie().set(Sprie::_1).sptie().set(Sptie::_1).speie().set(Speie::_1).spe().set(Spe::_1)
+            });
+        }
+
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            let errors = ERRORS.load(Ordering::Relaxed);
+            if errors & ERROR_MODE_FAULT != 0 {
+                Poll::Ready(Err(SpiError::ModeFault))
+            } else if errors & ERROR_OVERRUN != 0 {
+                Poll::Ready(Err(SpiError::Overrun))
+            } else if DONE.load(Ordering::Acquire) {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Clocks out `max(read.len(), write.len())` bytes, split into segments
+    /// the DTC can count.
+    async fn run(&mut self, read: *mut u8, read_len: usize, write: *const u8, write_len: usize) -> Result<(), SpiError> {
+        let common = read_len.min(write_len);
+        let total = read_len.max(write_len);
+        let mut offset = 0;
+        while offset < total {
+            let limit = if offset < common { common } else { total };
+            let len = (limit - offset).min(MAX_SEGMENT);
+            let (tx, tx_increment) = if offset < write_len {
+                (unsafe { write.add(offset) }, true)
+            } else {
+                (&FILL as *const u8, false)
+            };
+            let (rx, rx_increment) = if offset < read_len {
+                (unsafe { read.add(offset) }, true)
+            } else {
+                (SINK.as_ptr(), false)
+            };
+            self.segment(tx, tx_increment, rx, rx_increment, len).await?;
+            offset += len;
+        }
+        Ok(())
+    }
+}
+
+impl<'d> embedded_hal_async::spi::ErrorType for AsyncSpi0<'d> {
+    type Error = SpiError;
+}
+
+impl<'d> embedded_hal_async::spi::SpiBus<u8> for AsyncSpi0<'d> {
+    /// Clocks out 0xFF while reading.
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.run(words.as_mut_ptr(), words.len(), core::ptr::null(), 0).await
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.run(core::ptr::null_mut(), 0, words.as_ptr(), words.len()).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.run(read.as_mut_ptr(), read.len(), write.as_ptr(), write.len()).await
+    }
+
+    /// The receive side never overtakes the transmit side, so both DTC
+    /// transfers can share the buffer.
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let len = words.len();
+        let ptr = words.as_mut_ptr();
+        self.run(ptr, len, ptr, len).await
+    }
+
+    /// Nothing to wait for: each segment only resolves after the SPRI of
+    /// its last byte, which is clocked in after the last byte went out.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
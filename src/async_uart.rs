@@ -0,0 +1,476 @@
+//! Interrupt-driven UART for async executors, with DTC ring-buffered
+//! reception.
+//!
+//! Every SCI RXI event starts a DTC transfer that copies RDR into a caller
+//! supplied ring buffer, so no byte is lost while the executor is busy as
+//! long as the reader keeps up with the buffer size. The DTC also raises the
+//! CPU interrupt after each byte, which counts the bytes written and wakes
+//! the reading task; a writer that laps the reader is reported as
+//! `UartError::Overrun`. Transmission waits on the TXI / TEI interrupts.
+//!
+//! `read_until_idle` times the line idle with a GPT channel in one-shot
+//! mode, handed over with `set_idle_timer`: every RXI restarts the count and
+//! its overflow at the end of the idle time wakes the reader.
+//!
+//! The application routes the four SCI events to IELSR slots of its choice
+//! and forwards those interrupts to the `on_*` handlers:
+//!
+//! ```ignore
+//! #[interrupt]
+//! fn IEL0() {
+//!     AsyncUart::<Sci9>::on_rxi(interrupt::IEL0);
+//! }
+//!
+//! #[interrupt]
+//! fn IEL4() {
+//!     AsyncUart::<Sci9>::on_idle_timer(interrupt::IEL4);
+//! }
+//! ```
+
+use core::{future::poll_fn, marker::PhantomData, sync::atomic::Ordering, task::Poll};
+
+use cortex_m::interrupt::InterruptNumber;
+use embassy_time::Duration;
+use ra4m2_pac::{
+    gpt320::{gtcr::Cst, gtcsr::Cclr, gtpsr::Cstop, gtssr::Cstrt, gtuddtyc::{Ud, Udf}},
+    sci0::{scr::{Rie, Teie, Tie}, ssr::{Fer, Orer, Per}},
+    Gpt320 as GptRegs, Interrupt, NoBitfieldReg, RegisterValue,
+};
+
+use crate::{dtc::Dtc, gpt, icu, one_shot::MD_ONE_SHOT, sci::{self, Instance, RxdPin, TxdPin}, sysc::SystemClock, uart::{Uart, UartConfig, UartError}};
+
+// SSR error bits latched by `on_eri`
+const ERROR_OVERRUN: u8 = 1 << 5;
+const ERROR_FRAMING: u8 = 1 << 4;
+const ERROR_PARITY: u8 = 1 << 3;
+
+/// IELSR slots the application assigned to the channel's events.
+#[derive(Debug, Clone, Copy)]
+pub struct AsyncUartInterrupts {
+    pub rxi: Interrupt,
+    pub txi: Interrupt,
+    pub tei: Interrupt,
+    pub eri: Interrupt,
+}
+
+/// GPT channel timing the line idle, see `AsyncUart::set_idle_timer`.
+struct IdleTimer {
+    regs: fn() -> GptRegs,
+    max_count: u32,
+    /// Count clock frequency in Hz.
+    count_hz: u32,
+}
+
+/// GTPR for `idle_time` at `count_hz`, at least one count.
+fn idle_counts(idle_time: Duration, count_hz: u32) -> u64 {
+    (idle_time.as_micros() * count_hz as u64 / 1_000_000).max(1)
+}
+
+/// Async UART driver, generic over the SCI channel.
+pub struct AsyncUart<'d, T: Instance> {
+    _uart: Uart<'d, T>,
+    dtc: &'d mut Dtc,
+    interrupts: AsyncUartInterrupts,
+    // The DTC writes into the buffer behind our back, so it is only accessed
+    // through a raw pointer while the driver exists.
+    rx_buffer: *mut u8,
+    rx_len: usize,
+    read_pos: usize,
+    /// Bytes consumed since reception started, wrapping like
+    /// `State::rx_written`.
+    read_count: u32,
+    idle_time: Duration,
+    idle_timer: Option<IdleTimer>,
+    _rx_buffer: PhantomData<&'d mut [u8]>,
+}
+
+impl<'d, T: Instance> AsyncUart<'d, T> {
+    /// Creates the UART and starts continuous reception into `rx_buffer`
+    /// (1 to 256 bytes, all of which can hold unread data).
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<TX: TxdPin<T>, RX: RxdPin<T>>(
+        sci: T,
+        tx: &'d mut TX,
+        rx: &'d mut RX,
+        system_clock: &SystemClock,
+        config: UartConfig,
+        dtc: &'d mut Dtc,
+        rx_buffer: &'d mut [u8],
+        interrupts: AsyncUartInterrupts,
+    ) -> Self {
+        let uart = Uart::new(sci, tx, rx, system_clock, config);
+
+        let state = T::state();
+        state.rx_errors.store(0, Ordering::Relaxed);
+        state.rx_len.store(rx_buffer.len(), Ordering::Relaxed);
+        state.rx_last_pos.store(0, Ordering::Relaxed);
+        state.rx_written.store(0, Ordering::Relaxed);
+        state.rx_transfer.repeat_from_register(T::ADDRESS + sci::RDR_OFFSET, rx_buffer.as_mut_ptr(), rx_buffer.len());
+        dtc.attach(interrupts.rxi.number(), &state.rx_transfer);
+
+        icu::register_interrupt(interrupts.rxi, T::RXI_EVENT);
+        icu::enable_dtc(interrupts.rxi);
+        icu::register_interrupt(interrupts.txi, T::TXI_EVENT);
+        icu::register_interrupt(interrupts.tei, T::TEI_EVENT);
+        icu::register_interrupt(interrupts.eri, T::ERI_EVENT);
+
+        unsafe {
+            T::regs().scr().modify(|w| w.rie().set(Rie::_1));
+        }
+
+        AsyncUart {
+            _uart: uart,
+            dtc,
+            interrupts,
+            rx_buffer: rx_buffer.as_mut_ptr(),
+            rx_len: rx_buffer.len(),
+            read_pos: 0,
+            read_count: 0,
+            // Two characters of 10 bits
+            idle_time: Duration::from_micros(20_000_000 / config.baud_rate as u64),
+            idle_timer: None,
+            _rx_buffer: PhantomData,
+        }
+    }
+
+    /// Sets how long the line must stay quiet for `read_until_idle` to return.
+    /// Panics if an idle timer is set and the time doesn't fit its counter at
+    /// the current prescaler.
+    pub fn set_idle_time(&mut self, idle_time: Duration) {
+        if let Some(timer) = &self.idle_timer {
+            let gtpr = idle_counts(idle_time, timer.count_hz);
+            assert!(gtpr <= timer.max_count as u64, "idle time out of range for the prescaler");
+            unsafe {
+                (timer.regs)().gtpr().modify(|w| w.set(gtpr as u32));
+            }
+        }
+        self.idle_time = idle_time;
+    }
+
+    /// Times the line idle for `read_until_idle` with GPT channel `G`,
+    /// borrowed for `'d` like the pins. The channel's overflow event is
+    /// routed to `overflow`, whose interrupt must be forwarded to
+    /// `on_idle_timer`. Panics if the idle time doesn't fit the counter at any
+    /// prescaler.
+    pub fn set_idle_timer<G: gpt::Instance>(&mut self, _timer: &'d mut G, overflow: Interrupt, system_clock: &SystemClock) {
+        self.release_idle_timer();
+        cortex_m::interrupt::free(|cs| {
+            G::enable_power(cs);
+        });
+
+        let pclkd = system_clock.get_pclkd_freq();
+        let (tpcs, gtpr) = gpt::prescaler_setting(pclkd, G::MAX_COUNT, |count_hz| idle_counts(self.idle_time, count_hz))
+            .expect("idle time out of range for PCLKD");
+
+        unsafe {
+            let regs = G::regs();
+            regs.gtcr().modify(|w| w.set_raw(0));
+            regs.gtcr().modify(|w| w.md().set(MD_ONE_SHOT.into()).tpcs().set(tpcs.into()));
+            regs.gtuddtyc().modify(|w| w.set_raw(0).ud().set(Ud::_1).udf().set(Udf::_0));
+            regs.gtber().modify(|w| w.set_raw(0));
+            regs.gtior().modify(|w| w.set_raw(0));
+            regs.gtpr().modify(|w| w.set(gtpr));
+            regs.gtcnt().modify(|w| w.set(0));
+            regs.gtst().modify(|w| w.set_raw(0));
+            // Started and cleared by software only, from `on_rxi`
+            regs.gtssr().modify(|w| w.set_raw(0).cstrt().set(Cstrt::_1));
+            regs.gtpsr().modify(|w| w.set_raw(0).cstop().set(Cstop::_1));
+            regs.gtcsr().modify(|w| w.set_raw(0).cclr().set(Cclr::_1));
+        }
+
+        self.idle_timer = Some(IdleTimer {
+            regs: G::regs,
+            max_count: G::MAX_COUNT,
+            count_hz: pclkd / gpt::prescaler_divider(tpcs),
+        });
+        let state = T::state();
+        // Bytes received before the timer existed count as idle
+        state.rx_idle.store(true, Ordering::Relaxed);
+        cortex_m::interrupt::free(|cs| {
+            state.idle_timer.borrow(cs).set(Some((G::regs, 1 << G::CHANNEL)));
+        });
+        icu::register_interrupt(overflow, G::OVF_EVENT);
+    }
+
+    /// Stops the idle timer, if any, and detaches it from `on_rxi`.
+    fn release_idle_timer(&mut self) {
+        if let Some(timer) = self.idle_timer.take() {
+            cortex_m::interrupt::free(|cs| {
+                T::state().idle_timer.borrow(cs).set(None);
+            });
+            unsafe {
+                (timer.regs)().gtcr().modify(|w| w.cst().set(Cst::_0));
+            }
+        }
+    }
+
+    /// RXI handler: the DTC stored a byte.
+    pub fn on_rxi(interrupt: Interrupt) {
+        Self::track_writes();
+        Self::restart_idle_timer();
+        icu::clear_interrupt(interrupt);
+        T::state().rx_waker.wake();
+    }
+
+    /// Idle timer overflow handler: the line has been quiet for the idle
+    /// time. The one-shot counter has stopped by itself.
+    pub fn on_idle_timer(interrupt: Interrupt) {
+        T::state().rx_idle.store(true, Ordering::Relaxed);
+        icu::clear_interrupt(interrupt);
+        T::state().rx_waker.wake();
+    }
+
+    /// Counts the idle time again from the byte just received.
+    fn restart_idle_timer() {
+        let state = T::state();
+        if let Some((regs, mask)) = cortex_m::interrupt::free(|cs| state.idle_timer.borrow(cs).get()) {
+            state.rx_idle.store(false, Ordering::Relaxed);
+            unsafe {
+                let regs = regs();
+                regs.gtclr().init(|w| w.set_raw(mask));
+                regs.gtstr().init(|w| w.set_raw(mask));
+            }
+        }
+    }
+
+    /// TXI handler: the transmit data register is empty.
+    pub fn on_txi(interrupt: Interrupt) {
+        unsafe {
+            T::regs().scr().modify(|w| w.tie().set(Tie::_0));
+        }
+        icu::clear_interrupt(interrupt);
+        T::state().tx_waker.wake();
+    }
+
+    /// TEI handler: the last byte has left the shift register.
+    pub fn on_tei(interrupt: Interrupt) {
+        unsafe {
+            T::regs().scr().modify(|w| w.teie().set(Teie::_0));
+        }
+        icu::clear_interrupt(interrupt);
+        T::state().tx_waker.wake();
+    }
+
+    /// ERI handler: latches and clears the receive error flags. Reception
+    /// stops until ORER / FER / PER are cleared, so this must be serviced.
+    pub fn on_eri(interrupt: Interrupt) {
+        unsafe {
+            let ssr = T::regs().ssr().read().get_raw();
+            T::state()
+                .rx_errors
+                .fetch_or(ssr & (ERROR_OVERRUN | ERROR_FRAMING | ERROR_PARITY), Ordering::Relaxed);
+            T::regs().ssr().modify(|w| w.orer().set(Orer::_0).fer().set(Fer::_0).per().set(Per::_0));
+        }
+        icu::clear_interrupt(interrupt);
+        T::state().rx_waker.wake();
+    }
+
+    fn take_error(&self) -> Result<(), UartError> {
+        let errors = T::state().rx_errors.swap(0, Ordering::Relaxed);
+        if errors & ERROR_OVERRUN != 0 {
+            Err(UartError::Overrun)
+        } else if errors & ERROR_FRAMING != 0 {
+            Err(UartError::Framing)
+        } else if errors & ERROR_PARITY != 0 {
+            Err(UartError::Parity)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Adds the DTC's progress since the last call to `State::rx_written`
+    /// and returns the new total with the ring index it writes next. Runs on
+    /// every RXI, so the write position can't lap between calls unless the
+    /// interrupt is held off for a whole buffer.
+    fn track_writes() -> (u32, usize) {
+        let state = T::state();
+        cortex_m::interrupt::free(|_| {
+            let len = state.rx_len.load(Ordering::Relaxed);
+            let pos = (len - state.rx_transfer.repeat_remaining()) % len;
+            let last = state.rx_last_pos.swap(pos, Ordering::Relaxed);
+            let delta = ((pos + len - last) % len) as u32;
+            (state.rx_written.fetch_add(delta, Ordering::Relaxed).wrapping_add(delta), pos)
+        })
+    }
+
+    /// Received bytes not yet read, including any the DTC has overwritten.
+    fn unread(&self) -> usize {
+        Self::track_writes().0.wrapping_sub(self.read_count) as usize
+    }
+
+    /// Number of received bytes not yet read.
+    pub fn available(&self) -> usize {
+        self.unread().min(self.rx_len)
+    }
+
+    /// Fails if the DTC has overtaken the reader, dropping the unread bytes,
+    /// which are no longer in order.
+    fn check_overrun(&mut self) -> Result<(), UartError> {
+        let (written, pos) = Self::track_writes();
+        if written.wrapping_sub(self.read_count) as usize > self.rx_len {
+            self.read_count = written;
+            self.read_pos = pos;
+            return Err(UartError::Overrun);
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.read_pos = (self.read_pos + n) % self.rx_len;
+        self.read_count = self.read_count.wrapping_add(n as u32);
+    }
+
+    async fn wait_for_data(&mut self) -> Result<(), UartError> {
+        poll_fn(|cx| {
+            T::state().rx_waker.register(cx.waker());
+            self.take_error()?;
+            self.check_overrun()?;
+            if self.available() > 0 {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Longest contiguous run of unread bytes in the ring buffer.
+    fn contiguous(&self) -> &[u8] {
+        let len = self.available().min(self.rx_len - self.read_pos);
+        unsafe { core::slice::from_raw_parts(self.rx_buffer.add(self.read_pos), len) }
+    }
+
+    fn copy_out(&mut self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+        while count < buf.len() && self.available() > 0 {
+            let chunk = self.contiguous();
+            let n = chunk.len().min(buf.len() - count);
+            buf[count..count + n].copy_from_slice(&chunk[..n]);
+            self.advance(n);
+            count += n;
+        }
+        count
+    }
+
+    /// Waits for at least one byte, then keeps collecting until the line has
+    /// been idle for the configured idle time or `buf` is full. Suits framed
+    /// protocols (NMEA sentences, modem responses) without a length prefix.
+    ///
+    /// The task sleeps until the idle timer's overflow or a full buffer wakes
+    /// it. Panics if no idle timer was given with `set_idle_timer`.
+    pub async fn read_until_idle(&mut self, buf: &mut [u8]) -> Result<usize, UartError> {
+        assert!(self.idle_timer.is_some(), "read_until_idle needs an idle timer");
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.wait_for_data().await?;
+
+        let wanted = buf.len();
+        poll_fn(|cx| {
+            T::state().rx_waker.register(cx.waker());
+            self.take_error()?;
+            self.check_overrun()?;
+            if self.available() >= wanted || T::state().rx_idle.load(Ordering::Relaxed) {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await?;
+
+        let count = self.copy_out(buf);
+        // Bytes copied out while the DTC lapped the reader may be corrupt
+        self.check_overrun()?;
+        Ok(count)
+    }
+
+    async fn wait_transmit_buffer_ready(&mut self) {
+        poll_fn(|cx| {
+            T::state().tx_waker.register(cx.waker());
+            unsafe {
+                T::regs().scr().modify(|w| w.tie().set(Tie::_1));
+                // The buffer may have emptied before TIE was set, in which
+                // case no TXI follows
+                if T::regs().ssr().read().tdre().get().0 == 1 {
+                    T::regs().scr().modify(|w| w.tie().set(Tie::_0));
+                    return Poll::Ready(());
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    async fn wait_transmit_complete(&mut self) {
+        poll_fn(|cx| {
+            T::state().tx_waker.register(cx.waker());
+            unsafe {
+                T::regs().scr().modify(|w| w.teie().set(Teie::_1));
+                if T::regs().ssr().read().tend().get().0 == 1 {
+                    T::regs().scr().modify(|w| w.teie().set(Teie::_0));
+                    return Poll::Ready(());
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<'d, T: Instance> Drop for AsyncUart<'d, T> {
+    fn drop(&mut self) {
+        unsafe {
+            T::regs().scr().modify(|w| w.rie().set(Rie::_0).tie().set(Tie::_0).teie().set(Teie::_0));
+        }
+        // Re-registering without DTCE stops further transfers into the buffer
+        icu::register_interrupt(self.interrupts.rxi, T::RXI_EVENT);
+        self.dtc.detach(self.interrupts.rxi.number());
+        self.release_idle_timer();
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::ErrorType for AsyncUart<'d, T> {
+    type Error = UartError;
+}
+
+impl<'d, T: Instance> embedded_io_async::Read for AsyncUart<'d, T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.wait_for_data().await?;
+        let count = self.copy_out(buf);
+        self.check_overrun()?;
+        Ok(count)
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::BufRead for AsyncUart<'d, T> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        self.wait_for_data().await?;
+        Ok(self.contiguous())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.available());
+        self.advance(amt);
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::Write for AsyncUart<'d, T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for byte in buf {
+            self.wait_transmit_buffer_ready().await;
+            unsafe {
+                T::regs().tdr().modify(|w| w.set_raw(*byte));
+            }
+        }
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.wait_transmit_complete().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+//! Input capture on a GPT channel, measuring period and pulse width.
+//!
+//! The counter runs freely over its full range. The edge that starts a
+//! period is captured into GTCCRA and the opposite edge of the same pin into
+//! GTCCRB; the CCMPA / CCMPB handlers turn the captures into timestamps,
+//! extended past counter overflows by the OVF handler, so periods may span
+//! any number of overflows.
+//!
+//! The application routes the three events to IELSR slots of its choice and
+//! forwards those interrupts to the `on_*` handlers, as for
+//! `async_uart::AsyncUart`, in any priority order. Neither the OVF nor the
+//! capture interrupts may be held off for half a counter cycle.
+
+use core::{future::poll_fn, marker::PhantomData, task::Poll};
+
+use embassy_time::Duration;
+use ra4m2_pac::{gpt320::{gtcr::Cst, gtst::Tcfpo}, Interrupt, NoBitfieldReg, RegisterValue};
+
+use crate::{gpt::{self, GtiocaPin, GtiocbPin, Instance, Prescaler, GTCCRA, GTCCRB}, icu, sysc::SystemClock};
+
+// GTICASR/GTICBSR: capture on a GTIOCnA / GTIOCnB edge, whatever the level
+// of the other pin
+const A_RISING: u32 = 0b11 << 8;
+const A_FALLING: u32 = 0b11 << 10;
+const B_RISING: u32 = 0b11 << 12;
+const B_FALLING: u32 = 0b11 << 14;
+
+/// Edge that starts a period. The pulse width is measured from it to the
+/// opposite edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureEdge {
+    /// Measures high pulses.
+    Rising,
+    /// Measures low pulses.
+    Falling,
+}
+
+/// Sampling clock of the input noise filter (GTIOR.NFCSA/NFCSB), which
+/// requires three equal samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseFilter {
+    Pclkd = 0,
+    PclkdDiv4 = 1,
+    PclkdDiv16 = 2,
+    PclkdDiv64 = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureConfig {
+    pub edge: CaptureEdge,
+    /// Count clock; its period is the measurement resolution.
+    pub prescaler: Prescaler,
+    pub noise_filter: Option<NoiseFilter>,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            edge: CaptureEdge::Rising,
+            prescaler: Prescaler::Div1,
+            noise_filter: Some(NoiseFilter::Pclkd),
+        }
+    }
+}
+
+/// IELSR slots the application assigned to the channel's events.
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureInterrupts {
+    pub ccmpa: Interrupt,
+    pub ccmpb: Interrupt,
+    pub ovf: Interrupt,
+}
+
+/// One period of the input signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurement {
+    /// Period in count clock ticks.
+    pub period_ticks: u64,
+    /// Pulse width in count clock ticks.
+    pub pulse_width_ticks: u64,
+    pub period: Duration,
+    pub pulse_width: Duration,
+}
+
+impl Measurement {
+    /// Input frequency in Hz.
+    pub fn frequency(&self, tick_hz: u32) -> u32 {
+        (tick_hz as u64 / self.period_ticks.max(1)) as u32
+    }
+}
+
+/// Input capture driver on GPT channel `T`. The input pin is borrowed for
+/// `'d` the same way as for `i2c::I2c`.
+pub struct InputCapture<'d, T: Instance> {
+    gpt: T,
+    tick_hz: u32,
+    _pin: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> InputCapture<'d, T> {
+    /// Measures the signal on GTIOCnA.
+    pub fn new_a<P: GtiocaPin<T>>(gpt: T, _pin: &'d mut P, system_clock: &SystemClock, config: CaptureConfig, interrupts: CaptureInterrupts) -> Self {
+        let (start, end) = match config.edge {
+            CaptureEdge::Rising => (A_RISING, A_FALLING),
+            CaptureEdge::Falling => (A_FALLING, A_RISING),
+        };
+        let filter = config.noise_filter.map_or(0, |clock| 1 << 13 | (clock as u32) << 14);
+        Self::init(gpt, system_clock, config, interrupts, (start, end), filter)
+    }
+
+    /// Measures the signal on GTIOCnB.
+    pub fn new_b<P: GtiocbPin<T>>(gpt: T, _pin: &'d mut P, system_clock: &SystemClock, config: CaptureConfig, interrupts: CaptureInterrupts) -> Self {
+        let (start, end) = match config.edge {
+            CaptureEdge::Rising => (B_RISING, B_FALLING),
+            CaptureEdge::Falling => (B_FALLING, B_RISING),
+        };
+        let filter = config.noise_filter.map_or(0, |clock| 1 << 29 | (clock as u32) << 30);
+        Self::init(gpt, system_clock, config, interrupts, (start, end), filter)
+    }
+
+    fn init(gpt: T, system_clock: &SystemClock, config: CaptureConfig, interrupts: CaptureInterrupts, sources: (u32, u32), gtior: u32) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+            *T::state().capture.borrow(cs).borrow_mut() = Default::default();
+        });
+
+        unsafe {
+            let regs = T::regs();
+            regs.gtcr().modify(|w| w.set_raw(0));
+            // Saw-wave mode over the full range
+            regs.gtcr().modify(|w| w.tpcs().set((config.prescaler as u8).into()));
+            regs.gtber().modify(|w| w.set_raw(0));
+            regs.gtpr().modify(|w| w.set(T::MAX_COUNT));
+            regs.gtcnt().modify(|w| w.set(0));
+            // Both pins inputs, with the noise filter on the measured one
+            regs.gtior().modify(|w| w.set_raw(gtior));
+            regs.gticasr().modify(|w| w.set_raw(sources.0));
+            regs.gticbsr().modify(|w| w.set_raw(sources.1));
+            regs.gtst().modify(|w| w.set_raw(0));
+        }
+
+        icu::register_interrupt(interrupts.ccmpa, T::CCMPA_EVENT);
+        icu::register_interrupt(interrupts.ccmpb, T::CCMPB_EVENT);
+        icu::register_interrupt(interrupts.ovf, T::OVF_EVENT);
+
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_1));
+        }
+
+        InputCapture {
+            gpt,
+            tick_hz: system_clock.get_pclkd_freq() / gpt::prescaler_divider(config.prescaler as u8),
+            _pin: PhantomData,
+        }
+    }
+
+    /// Stops the counter and releases the channel.
+    pub fn free(self) -> T {
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_0));
+            T::regs().gticasr().modify(|w| w.set_raw(0));
+            T::regs().gticbsr().modify(|w| w.set_raw(0));
+        }
+        self.gpt
+    }
+
+    /// Count clock frequency in Hz.
+    pub fn tick_hz(&self) -> u32 {
+        self.tick_hz
+    }
+
+    /// Converts count clock ticks to a duration.
+    pub fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        Duration::from_nanos((ticks as u128 * 1_000_000_000 / self.tick_hz as u128) as u64)
+    }
+
+    fn measurement(&self, (period_ticks, pulse_width_ticks): (u64, u64)) -> Measurement {
+        Measurement {
+            period_ticks,
+            pulse_width_ticks,
+            period: self.ticks_to_duration(period_ticks),
+            pulse_width: self.ticks_to_duration(pulse_width_ticks),
+        }
+    }
+
+    /// The latest complete measurement, if any.
+    pub fn last(&self) -> Option<Measurement> {
+        let ticks = cortex_m::interrupt::free(|cs| T::state().capture.borrow(cs).borrow().measurement);
+        ticks.map(|ticks| self.measurement(ticks))
+    }
+
+    /// Waits for the next complete measurement.
+    pub async fn next(&mut self) -> Measurement {
+        let sequence = cortex_m::interrupt::free(|cs| T::state().capture.borrow(cs).borrow().sequence);
+        let ticks = poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+            cortex_m::interrupt::free(|cs| {
+                let capture = T::state().capture.borrow(cs).borrow();
+                match capture.measurement {
+                    Some(ticks) if capture.sequence != sequence => Poll::Ready(ticks),
+                    _ => Poll::Pending,
+                }
+            })
+        })
+        .await;
+        self.measurement(ticks)
+    }
+
+    /// Extends a captured counter value with the overflow count. The
+    /// current counter value is extended first, where a pending overflow can
+    /// only have happened just before the read, and the capture is dated back
+    /// from it. This holds whether or not the OVF handler has already counted
+    /// a wrap that followed the capture, as long as the capture is handled
+    /// within one counter cycle.
+    fn timestamp(cs: &cortex_m::interrupt::CriticalSection, index: usize) -> u64 {
+        let range = T::MAX_COUNT as u64 + 1;
+        let (value, count, overflow_pending) = unsafe {
+            let regs = T::regs();
+            (regs.gtccr().get(index).read().get(), regs.gtcnt().read().get(), regs.gtst().read().tcfpo().get().0 == 1)
+        };
+        let mut overflows = T::state().capture.borrow(cs).borrow().overflows as u64;
+        if overflow_pending && count <= T::MAX_COUNT / 2 {
+            overflows += 1;
+        }
+        let now = overflows * range + count as u64;
+        let elapsed = (count as u64 + range - value as u64) % range;
+        now.wrapping_sub(elapsed)
+    }
+
+    /// CCMPA handler: an edge starting a period.
+    pub fn on_ccmpa(interrupt: Interrupt) {
+        cortex_m::interrupt::free(|cs| {
+            let time = Self::timestamp(cs, GTCCRA);
+            let mut capture = T::state().capture.borrow(cs).borrow_mut();
+            capture.period = capture.start.map(|start| time.wrapping_sub(start));
+            capture.start = Some(time);
+        });
+        icu::clear_interrupt(interrupt);
+    }
+
+    /// CCMPB handler: the edge ending a pulse, completing a measurement.
+    pub fn on_ccmpb(interrupt: Interrupt) {
+        let complete = cortex_m::interrupt::free(|cs| {
+            let time = Self::timestamp(cs, GTCCRB);
+            let mut capture = T::state().capture.borrow(cs).borrow_mut();
+            match (capture.start, capture.period) {
+                (Some(start), Some(period)) => {
+                    capture.measurement = Some((period, time.wrapping_sub(start)));
+                    capture.sequence = capture.sequence.wrapping_add(1);
+                    true
+                }
+                _ => false,
+            }
+        });
+        icu::clear_interrupt(interrupt);
+        if complete {
+            T::state().waker.wake();
+        }
+    }
+
+    /// OVF handler: counts counter overflows.
+    pub fn on_ovf(interrupt: Interrupt) {
+        cortex_m::interrupt::free(|cs| {
+            unsafe {
+                T::regs().gtst().modify(|w| w.tcfpo().set(Tcfpo::_0));
+            }
+            let mut capture = T::state().capture.borrow(cs).borrow_mut();
+            capture.overflows = capture.overflows.wrapping_add(1);
+        });
+        icu::clear_interrupt(interrupt);
+    }
+}
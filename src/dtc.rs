@@ -0,0 +1,182 @@
+//! Data Transfer Controller (DTC).
+//!
+//! Any ICU event routed to the DTC (`icu::enable_dtc`) starts the transfer
+//! described by the transfer information block its IELSR slot's vector points
+//! at. The vector table lives in RAM here; drivers own their transfer
+//! information blocks, which must stay at a fixed address while attached.
+//!
+//! The DTC shares module stop bit MSTPCRA.MSTPA22 with the DMAC; it is
+//! released from module stop at reset, so no power control is needed.
+
+use core::cell::UnsafeCell;
+
+use ra4m2_pac::{dtc::dtcst::Dtcst, NoBitfieldReg};
+
+/// Number of DTC vectors, one per IELSR slot.
+const VECTORS: usize = 96;
+
+/// DTCVBR requires the table to be 1 KiB aligned.
+#[repr(C, align(1024))]
+struct VectorTable(UnsafeCell<[u32; VECTORS]>);
+
+// Only written through `attach`/`detach` inside critical sections and read by
+// the DTC hardware.
+unsafe impl Sync for VectorTable {}
+
+static VECTOR_TABLE: VectorTable = VectorTable(UnsafeCell::new([0; VECTORS]));
+
+// MRA: MD (7:6), SZ (5:4), SM (3:2). MRB: CHNE (7), CHNS (6), DISEL (5),
+// DTS (4), DM (3:2). See "Transfer Information" in the DTC chapter.
+const MRA_MODE_NORMAL: u32 = 0b00 << 6;
+const MRA_MODE_REPEAT: u32 = 0b01 << 6;
+const MRA_SIZE_BYTE: u32 = 0b00 << 4;
+const MRA_SRC_FIXED: u32 = 0b00 << 2;
+const MRA_SRC_INCREMENT: u32 = 0b10 << 2;
+const MRB_INTERRUPT_EVERY_TRANSFER: u32 = 1 << 5;
+const MRB_REPEAT_AREA_SRC: u32 = 1 << 4;
+const MRB_DST_FIXED: u32 = 0b00 << 2;
+const MRB_DST_INCREMENT: u32 = 0b10 << 2;
+
+/// One transfer information block in full-address mode: MRA/MRB, SAR, DAR,
+/// CRA/CRB. The DTC writes the updated addresses and counts back after each
+/// transfer.
+#[repr(C, align(4))]
+pub struct TransferInfo {
+    words: UnsafeCell<[u32; 4]>,
+}
+
+// Shared between the owning driver and the DTC hardware; accessed volatile.
+unsafe impl Sync for TransferInfo {}
+
+impl TransferInfo {
+    pub const fn new() -> Self {
+        TransferInfo {
+            words: UnsafeCell::new([0; 4]),
+        }
+    }
+
+    fn write(&self, mra: u32, mrb: u32, sar: u32, dar: u32, cra: u16, crb: u16) {
+        let words = self.words.get() as *mut u32;
+        unsafe {
+            words.write_volatile((mra << 24) | (mrb << 16));
+            words.add(1).write_volatile(sar);
+            words.add(2).write_volatile(dar);
+            words.add(3).write_volatile(((cra as u32) << 16) | crb as u32);
+        }
+    }
+
+    /// Repeat mode, byte-sized, from a fixed peripheral register into a ring
+    /// buffer of `len` (1..=256) bytes. The destination wraps back to the
+    /// start of the buffer after `len` transfers and the CPU interrupt fires
+    /// after every transfer.
+    pub fn repeat_from_register(&self, register: u32, buffer: *mut u8, len: usize) {
+        assert!((1..=256).contains(&len));
+        // CRAH holds the reload value, CRAL the running count; 256 is encoded as 0
+        let count = (len & 0xFF) as u16;
+        self.write(
+            MRA_MODE_REPEAT | MRA_SIZE_BYTE | MRA_SRC_FIXED,
+            MRB_INTERRUPT_EVERY_TRANSFER | MRB_DST_INCREMENT,
+            register,
+            buffer as u32,
+            (count << 8) | count,
+            1,
+        );
+    }
+
+    /// Repeat mode, byte-sized, from a buffer of `len` (1..=256) bytes into a
+    /// fixed peripheral register. The source wraps after `len` transfers.
+    pub fn repeat_to_register(&self, buffer: *const u8, len: usize, register: u32) {
+        assert!((1..=256).contains(&len));
+        let count = (len & 0xFF) as u16;
+        self.write(
+            MRA_MODE_REPEAT | MRA_SIZE_BYTE | MRA_SRC_INCREMENT,
+            MRB_INTERRUPT_EVERY_TRANSFER | MRB_REPEAT_AREA_SRC | MRB_DST_FIXED,
+            buffer as u32,
+            register,
+            (count << 8) | count,
+            1,
+        );
+    }
+
+    /// Normal mode, byte-sized, from a fixed peripheral register into
+    /// `len` (1..=65535) bytes at `buffer`, or all into the one byte at
+    /// `buffer` if `increment` is false. The CPU interrupt fires once, after
+    /// the last transfer.
+    pub fn normal_from_register(&self, register: u32, buffer: *mut u8, increment: bool, len: usize) {
+        assert!((1..=0xFFFF).contains(&len));
+        self.write(
+            MRA_MODE_NORMAL | MRA_SIZE_BYTE | MRA_SRC_FIXED,
+            if increment { MRB_DST_INCREMENT } else { MRB_DST_FIXED },
+            register,
+            buffer as u32,
+            len as u16,
+            0,
+        );
+    }
+
+    /// Normal mode, byte-sized, from `len` (1..=65535) bytes at `buffer`, or
+    /// the one byte at `buffer` repeatedly if `increment` is false, into a
+    /// fixed peripheral register. The CPU interrupt fires once, after the
+    /// last transfer.
+    pub fn normal_to_register(&self, buffer: *const u8, increment: bool, len: usize, register: u32) {
+        assert!((1..=0xFFFF).contains(&len));
+        self.write(
+            MRA_MODE_NORMAL | MRA_SIZE_BYTE | if increment { MRA_SRC_INCREMENT } else { MRA_SRC_FIXED },
+            MRB_DST_FIXED,
+            buffer as u32,
+            register,
+            len as u16,
+            0,
+        );
+    }
+
+    /// Transfers left before the repeat area wraps (CRAL), as 1..=256.
+    pub fn repeat_remaining(&self) -> usize {
+        let words = self.words.get() as *const u32;
+        let cral = unsafe { (words.add(3).read_volatile() >> 16) & 0xFF } as usize;
+        if cral == 0 { 256 } else { cral }
+    }
+}
+
+impl Default for TransferInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Takes control of the DTC and points it at the RAM vector table.
+pub struct Dtc {
+    _dtc: ra4m2_pac::Dtc,
+}
+
+impl Dtc {
+    pub fn new(dtc: ra4m2_pac::Dtc) -> Self {
+        unsafe {
+            dtc.dtcvbr().modify(|w| w.set(VECTOR_TABLE.0.get() as u32));
+            dtc.dtcst().modify(|w| w.dtcst().set(Dtcst::_1));
+        }
+        cortex_m::asm::dsb();
+
+        Dtc { _dtc: dtc }
+    }
+
+    /// Points the vector for IELSR slot `slot` at `info`. The caller must keep
+    /// `info` in place until `detach` is called for the slot.
+    pub fn attach(&mut self, slot: u16, info: &'static TransferInfo) {
+        assert!((slot as usize) < VECTORS);
+        cortex_m::interrupt::free(|_| unsafe {
+            let vectors = VECTOR_TABLE.0.get() as *mut u32;
+            vectors.add(slot as usize).write_volatile(info as *const TransferInfo as u32);
+        });
+        cortex_m::asm::dsb();
+    }
+
+    /// Clears the vector for IELSR slot `slot`.
+    pub fn detach(&mut self, slot: u16) {
+        assert!((slot as usize) < VECTORS);
+        cortex_m::interrupt::free(|_| unsafe {
+            let vectors = VECTOR_TABLE.0.get() as *mut u32;
+            vectors.add(slot as usize).write_volatile(0);
+        });
+    }
+}
@@ -0,0 +1,231 @@
+//! Quadrature encoder (phase counting) on a GPT channel.
+//!
+//! GTIOCnA and GTIOCnB take the encoder's A and B phases, and the counter
+//! steps up or down on their edges (GTUPSR/GTDNSR) instead of on the count
+//! clock. The OVF / UDF handlers extend the count past the counter's range,
+//! so `position` is a signed count since start-up.
+//!
+//! With an index input the counter is cleared on each rising edge of the
+//! index (Z) pin, a GTETRG input, and `position` is the count since the
+//! last index pulse instead; one revolution must fit the counter.
+//!
+//! The application routes the OVF and UDF events to IELSR slots of its
+//! choice and forwards those interrupts to the `on_*` handlers, as for
+//! `async_uart::AsyncUart`.
+
+use core::{marker::PhantomData, sync::atomic::Ordering};
+
+use embassy_time::Instant;
+use ra4m2_pac::{gpt320::{gtcr::Cst, gtst::{Tcfpo, Tcfpu}}, Interrupt, NoBitfieldReg, RegisterValue};
+
+use crate::{capture::NoiseFilter, gpt::{GtiocaPin, GtiocbPin, Instance, TriggerSource}, icu, poeg::GtetrgPin};
+
+// GTUPSR/GTDNSR: count on an edge of one phase at a given level of the other
+const A_RISING_B_LOW: u32 = 1 << 8;
+const A_RISING_B_HIGH: u32 = 1 << 9;
+const A_FALLING_B_LOW: u32 = 1 << 10;
+const A_FALLING_B_HIGH: u32 = 1 << 11;
+const B_RISING_A_LOW: u32 = 1 << 12;
+const B_RISING_A_HIGH: u32 = 1 << 13;
+const B_FALLING_A_LOW: u32 = 1 << 14;
+const B_FALLING_A_HIGH: u32 = 1 << 15;
+
+/// Count sources, after the phase counting modes of the RA4M2 manual. In the
+/// quadrature modes A leading B counts up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderMode {
+    /// Mode 1: every edge of both phases, four counts per cycle.
+    Quadrature4x,
+    /// Mode 2: both edges of A, two counts per cycle.
+    Quadrature2x,
+    /// Mode 3: one edge of A, one count per cycle.
+    Quadrature1x,
+    /// Mode 4: rising edges of A are counted, B selects the direction (low
+    /// counts up).
+    PulseDirection,
+    /// Mode 5: rising edges of A count up, rising edges of B count down.
+    UpDown,
+}
+
+impl EncoderMode {
+    /// GTUPSR and GTDNSR settings.
+    fn sources(self) -> (u32, u32) {
+        match self {
+            EncoderMode::Quadrature4x => (
+                A_RISING_B_LOW | B_RISING_A_HIGH | A_FALLING_B_HIGH | B_FALLING_A_LOW,
+                B_RISING_A_LOW | A_RISING_B_HIGH | B_FALLING_A_HIGH | A_FALLING_B_LOW,
+            ),
+            EncoderMode::Quadrature2x => (A_RISING_B_LOW | A_FALLING_B_HIGH, A_RISING_B_HIGH | A_FALLING_B_LOW),
+            EncoderMode::Quadrature1x => (A_RISING_B_LOW, A_FALLING_B_LOW),
+            EncoderMode::PulseDirection => (A_RISING_B_LOW, A_RISING_B_HIGH),
+            EncoderMode::UpDown => (A_RISING_B_LOW | A_RISING_B_HIGH, B_RISING_A_LOW | B_RISING_A_HIGH),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderConfig {
+    pub mode: EncoderMode,
+    /// Noise filter on both phase inputs.
+    pub noise_filter: Option<NoiseFilter>,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig {
+            mode: EncoderMode::Quadrature4x,
+            noise_filter: Some(NoiseFilter::PclkdDiv4),
+        }
+    }
+}
+
+/// IELSR slots the application assigned to the channel's events.
+#[derive(Debug, Clone, Copy)]
+pub struct EncoderInterrupts {
+    pub ovf: Interrupt,
+    pub udf: Interrupt,
+}
+
+/// Encoder driver on GPT channel `T`. Pins are borrowed for `'d` the same
+/// way as for `i2c::I2c`.
+pub struct Encoder<'d, T: Instance> {
+    gpt: T,
+    indexed: bool,
+    /// Position and time of the previous `velocity` call.
+    last_sample: Option<(i64, Instant)>,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> Encoder<'d, T> {
+    pub fn new<A: GtiocaPin<T>, B: GtiocbPin<T>>(
+        gpt: T,
+        _a: &'d mut A,
+        _b: &'d mut B,
+        config: EncoderConfig,
+        interrupts: EncoderInterrupts,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+        T::state().wraps.store(0, Ordering::Relaxed);
+
+        let (up, down) = config.mode.sources();
+        // NFAEN/NFCSA and NFBEN/NFCSB
+        let filter = config.noise_filter.map_or(0, |clock| {
+            let clock = clock as u32;
+            1 << 13 | clock << 14 | 1 << 29 | clock << 30
+        });
+
+        unsafe {
+            let regs = T::regs();
+            regs.gtcr().modify(|w| w.set_raw(0));
+            regs.gtber().modify(|w| w.set_raw(0));
+            regs.gtpr().modify(|w| w.set(T::MAX_COUNT));
+            regs.gtcnt().modify(|w| w.set(0));
+            regs.gtior().modify(|w| w.set_raw(filter));
+            regs.gtupsr().modify(|w| w.set_raw(up));
+            regs.gtdnsr().modify(|w| w.set_raw(down));
+            regs.gtcsr().modify(|w| w.set_raw(0));
+            regs.gtst().modify(|w| w.set_raw(0));
+        }
+
+        icu::register_interrupt(interrupts.ovf, T::OVF_EVENT);
+        icu::register_interrupt(interrupts.udf, T::UDF_EVENT);
+
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_1));
+        }
+
+        Encoder {
+            gpt,
+            indexed: false,
+            last_sample: None,
+            _pins: PhantomData,
+        }
+    }
+
+    /// Stops counting and releases the channel.
+    pub fn free(self) -> T {
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_0));
+            T::regs().gtupsr().modify(|w| w.set_raw(0));
+            T::regs().gtdnsr().modify(|w| w.set_raw(0));
+            T::regs().gtcsr().modify(|w| w.set_raw(0));
+        }
+        self.gpt
+    }
+
+    /// Clears the count on rising edges of the index pin, which must be muxed
+    /// to `PinFunction::GPTA`.
+    pub fn set_index<P: GtetrgPin>(&mut self, _pin: &'d mut P) {
+        self.indexed = true;
+        let bit = TriggerSource::GtetrgRising(P::GROUP).bit();
+        unsafe {
+            T::regs().gtcsr().modify(|w| w.set_raw(w.get_raw() | bit));
+        }
+    }
+
+    /// Sets the current position to zero.
+    pub fn reset(&mut self) {
+        cortex_m::interrupt::free(|_| unsafe {
+            T::regs().gtcnt().modify(|w| w.set(0));
+            T::regs().gtst().modify(|w| w.tcfpo().set(Tcfpo::_0).tcfpu().set(Tcfpu::_0));
+            T::state().wraps.store(0, Ordering::Relaxed);
+        });
+        self.last_sample = None;
+    }
+
+    /// Signed count since start-up or `reset`, or since the last index
+    /// pulse with an index input.
+    pub fn position(&self) -> i64 {
+        let range = T::MAX_COUNT as i64 + 1;
+        cortex_m::interrupt::free(|_| {
+            let (count, status) = unsafe { (T::regs().gtcnt().read().get() as i64, T::regs().gtst().read()) };
+            if self.indexed {
+                // Counting down from the index wraps to the top of the range
+                return if count > range / 2 { count - range } else { count };
+            }
+
+            let mut wraps = T::state().wraps.load(Ordering::Relaxed) as i64;
+            // Wraps whose handler hasn't run yet
+            if status.tcfpo().get().0 == 1 && count < range / 2 {
+                wraps += 1;
+            }
+            if status.tcfpu().get().0 == 1 && count >= range / 2 {
+                wraps -= 1;
+            }
+            wraps * range + count
+        })
+    }
+
+    /// Counts per second since the previous call, or `None` on the first
+    /// call.
+    pub fn velocity(&mut self) -> Option<i32> {
+        let now = Instant::now();
+        let position = self.position();
+        let velocity = self.last_sample.and_then(|(last_position, last_time)| {
+            let elapsed = now.duration_since(last_time).as_micros() as i64;
+            (elapsed > 0).then(|| ((position - last_position) * 1_000_000 / elapsed) as i32)
+        });
+        self.last_sample = Some((position, now));
+        velocity
+    }
+
+    /// OVF handler: the count wrapped past the top of the range.
+    pub fn on_ovf(interrupt: Interrupt) {
+        cortex_m::interrupt::free(|_| unsafe {
+            T::regs().gtst().modify(|w| w.tcfpo().set(Tcfpo::_0));
+            T::state().wraps.fetch_add(1, Ordering::Relaxed);
+        });
+        icu::clear_interrupt(interrupt);
+    }
+
+    /// UDF handler: the count wrapped below zero.
+    pub fn on_udf(interrupt: Interrupt) {
+        cortex_m::interrupt::free(|_| unsafe {
+            T::regs().gtst().modify(|w| w.tcfpu().set(Tcfpu::_0));
+            T::state().wraps.fetch_sub(1, Ordering::Relaxed);
+        });
+        icu::clear_interrupt(interrupt);
+    }
+}
@@ -71,11 +71,13 @@ pub enum PinFunction {
     GPTB = 3,
     SCIA = 4,
     SCIB = 5,
+    SPI = 6,
     IIC = 7,
     RTC = 9,
     ADC = 10,
     CTSU = 12,
     CAN = 16,
+    QSPI = 17,
     SSIE = 18,
     USBFS = 19,
     SDHI = 21,
@@ -192,13 +194,27 @@ macro_rules! gpio_port {
                     Pin { _p: PhantomData }
                 }
 
+                /// Hands the pin to a peripheral with an open-drain, low drive
+                /// output, as needed for I2C. Use `into_alternate_function_with`
+                /// for push-pull peripherals (SCI TX, SPI, GPT PWM, ...).
                 pub fn into_alternate_function(self, function: PinFunction) -> Pin<Output<AlternateFunction>, N> {
+                    self.into_alternate_function_with(function, DrainControl::OpenDrain, DriveMode::Low)
+                }
+
+                /// Hands the pin to a peripheral with the given output driver
+                /// settings.
+                pub fn into_alternate_function_with(
+                    self,
+                    function: PinFunction,
+                    drain: DrainControl,
+                    drive_mode: DriveMode,
+                ) -> Pin<Output<AlternateFunction>, N> {
                     crate::pfsel::$mod_name::set_pin_function(
                         N,
                         PortDirection::Output,
                         PullUpMode::Disabled,
-                        DrainControl::OpenDrain,
-                        DriveMode::Low,
+                        drain,
+                        drive_mode,
                         InterruptEvent::DontCare,
                         InterruptEnable::Disabled,
                         AnalogInput::Disabled,
@@ -0,0 +1,392 @@
+//! General PWM Timer (GPT) channels shared by the timer drivers (PWM, ...).
+//!
+//! The RA4M2 has four 32-bit channels (GPT320-GPT323) and six 16-bit ones
+//! (GPT164-GPT169), numbered 0-9 here and each behind a Cargo feature
+//! `gpt0`..`gpt9`. All channels share one register layout, and the 16-bit
+//! channels simply lack the upper counter bits. Counters run from PCLKD
+//! through a 1/4/16/64/256/1024 prescaler.
+//!
+//! `ChannelSet` starts, stops and clears several channels in the same PCLKD
+//! cycle, from software through the GTSTR/GTSTP/GTCLR registers common to
+//! all channels, or from hardware triggers: GTETRG pins, or any event routed
+//! through the ELC (including another channel's compare match or overflow).
+
+use core::{cell::RefCell, sync::atomic::AtomicI32};
+
+use cortex_m::interrupt::Mutex;
+use ra4m2_pac::{elc::elcr::Elcon, gpt320::{gtcsr::Cclr, gtpsr::Cstop, gtssr::Cstrt}, Gpt320 as GptRegs, NoBitfieldReg, RegisterValue};
+
+use crate::{gpio::{AlternateFunction, Output}, poeg, power, sealed, waker::WakerSlot};
+
+/// Indices of the compare/capture registers in GTCCR. The registers are laid
+/// out A, B, C, E, D, F: C and E are the single buffers of A and B, D and F
+/// their double buffers.
+pub(crate) const GTCCRA: usize = 0;
+pub(crate) const GTCCRB: usize = 1;
+pub(crate) const GTCCRC: usize = 2;
+pub(crate) const GTCCRE: usize = 3;
+
+/// Edge timestamps of an input capture, extended past counter overflows.
+#[doc(hidden)]
+#[derive(Default)]
+pub struct Capture {
+    pub(crate) overflows: u32,
+    /// Timestamp of the last edge starting a period.
+    pub(crate) start: Option<u64>,
+    /// Ticks between the last two starting edges.
+    pub(crate) period: Option<u64>,
+    /// Latest complete measurement, as (period, pulse width) ticks.
+    pub(crate) measurement: Option<(u64, u64)>,
+    /// Counts completed measurements.
+    pub(crate) sequence: u32,
+}
+
+/// Interrupt-shared state of one channel, used by the interrupt-driven
+/// drivers.
+#[doc(hidden)]
+pub struct State {
+    pub(crate) waker: WakerSlot,
+    pub(crate) capture: Mutex<RefCell<Capture>>,
+    /// Net counter overflows less underflows, for phase counting.
+    pub(crate) wraps: AtomicI32,
+}
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        State {
+            waker: WakerSlot::new(),
+            capture: Mutex::new(RefCell::new(Capture {
+                overflows: 0,
+                start: None,
+                period: None,
+                measurement: None,
+                sequence: 0,
+            })),
+            wraps: AtomicI32::new(0),
+        }
+    }
+}
+
+/// A GPT channel. Sealed so drivers can rely on the register layout and
+/// event numbers.
+pub trait Instance: sealed::Sealed {
+    /// Channel number, 0-9. Selects the channel's bit in the GTSTR/GTSTP/GTCLR
+    /// registers shared by all channels.
+    const CHANNEL: u8;
+    /// Largest counter value: `u32::MAX` or `u16::MAX`.
+    const MAX_COUNT: u32;
+    /// ICU event number for GTCCRA compare match / input capture (CCMPA).
+    const CCMPA_EVENT: u16;
+    /// ICU event number for GTCCRB compare match / input capture (CCMPB).
+    const CCMPB_EVENT: u16;
+    /// ICU event number for counter overflow (OVF).
+    const OVF_EVENT: u16;
+    /// ICU event number for counter underflow (UDF).
+    const UDF_EVENT: u16;
+
+    /// Register block of this channel.
+    fn regs() -> GptRegs;
+
+    /// Releases the channel from module stop.
+    fn enable_power(cs: &cortex_m::interrupt::CriticalSection);
+
+    /// Interrupt-shared state of this channel.
+    #[doc(hidden)]
+    fn state() -> &'static State;
+}
+
+// Each channel's events start at 0x075 + 10 * n: CCMPA, CCMPB, CMPC, CMPD,
+// CMPE, CMPF, OVF, UDF, ADTRA, ADTRB.
+macro_rules! gpt_instance {
+    ($feature:literal, $name:ident, $regs:ident, $channel:literal, $max:expr) => {
+        /// Owned GPT channel, created from its PAC token and handing it back
+        /// from `free`.
+        #[cfg(feature = $feature)]
+        pub struct $name {
+            gpt: GptRegs,
+        }
+
+        #[cfg(feature = $feature)]
+        impl $name {
+            /// Panics on another channel's token.
+            pub fn new(gpt: GptRegs) -> Self {
+                assert!(gpt == ra4m2_pac::$regs, "PAC token of another GPT channel");
+                $name { gpt }
+            }
+
+            /// Returns the PAC token.
+            pub fn free(self) -> GptRegs {
+                self.gpt
+            }
+        }
+
+        #[cfg(feature = $feature)]
+        impl sealed::Sealed for $name {}
+
+        #[cfg(feature = $feature)]
+        impl Instance for $name {
+            const CHANNEL: u8 = $channel;
+            const MAX_COUNT: u32 = $max;
+            const CCMPA_EVENT: u16 = 0x075 + 10 * $channel;
+            const CCMPB_EVENT: u16 = 0x076 + 10 * $channel;
+            const OVF_EVENT: u16 = 0x07B + 10 * $channel;
+            const UDF_EVENT: u16 = 0x07C + 10 * $channel;
+
+            fn regs() -> GptRegs {
+                ra4m2_pac::$regs
+            }
+
+            fn enable_power(cs: &cortex_m::interrupt::CriticalSection) {
+                power::enable_gpt(cs, $channel);
+            }
+
+            fn state() -> &'static State {
+                static STATE: State = State::new();
+                &STATE
+            }
+        }
+    };
+}
+
+gpt_instance!("gpt0", Gpt0, GPT320, 0, u32::MAX);
+gpt_instance!("gpt1", Gpt1, GPT321, 1, u32::MAX);
+gpt_instance!("gpt2", Gpt2, GPT322, 2, u32::MAX);
+gpt_instance!("gpt3", Gpt3, GPT323, 3, u32::MAX);
+gpt_instance!("gpt4", Gpt4, GPT164, 4, u16::MAX as u32);
+gpt_instance!("gpt5", Gpt5, GPT165, 5, u16::MAX as u32);
+gpt_instance!("gpt6", Gpt6, GPT166, 6, u16::MAX as u32);
+gpt_instance!("gpt7", Gpt7, GPT167, 7, u16::MAX as u32);
+gpt_instance!("gpt8", Gpt8, GPT168, 8, u16::MAX as u32);
+gpt_instance!("gpt9", Gpt9, GPT169, 9, u16::MAX as u32);
+
+/// Marks a pin that can carry GTIOCnA of GPT channel `T`. Pins must be muxed
+/// to `PinFunction::GPTB` first, with `into_alternate_function_with` and
+/// `DrainControl::PushPull` when used as outputs.
+pub trait GtiocaPin<T: Instance> {}
+/// Marks a pin that can carry GTIOCnB of GPT channel `T`.
+pub trait GtiocbPin<T: Instance> {}
+
+macro_rules! impl_gpt_pin {
+    ($feature:literal, $port:ident, $n:literal, $pin_trait:ident, $instance:ident, $instance_feature:literal) => {
+        #[cfg(all(feature = $feature, feature = $instance_feature))]
+        impl $pin_trait<$instance> for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {}
+    };
+}
+
+// Pin assignments from the "Peripheral Select Settings" tables of the
+// RA4M2 User's Manual.
+impl_gpt_pin!("port4", port4, 15, GtiocaPin, Gpt0, "gpt0"); // GTIOC0A_A
+impl_gpt_pin!("port4", port4, 14, GtiocbPin, Gpt0, "gpt0"); // GTIOC0B_A
+impl_gpt_pin!("port1", port1, 5, GtiocaPin, Gpt1, "gpt1"); // GTIOC1A_A
+impl_gpt_pin!("port1", port1, 4, GtiocbPin, Gpt1, "gpt1"); // GTIOC1B_A
+impl_gpt_pin!("port1", port1, 3, GtiocaPin, Gpt2, "gpt2"); // GTIOC2A_A
+impl_gpt_pin!("port1", port1, 2, GtiocbPin, Gpt2, "gpt2"); // GTIOC2B_A
+impl_gpt_pin!("port1", port1, 11, GtiocaPin, Gpt3, "gpt3"); // GTIOC3A_A
+impl_gpt_pin!("port1", port1, 12, GtiocbPin, Gpt3, "gpt3"); // GTIOC3B_A
+impl_gpt_pin!("port3", port3, 2, GtiocaPin, Gpt4, "gpt4"); // GTIOC4A_A
+impl_gpt_pin!("port3", port3, 1, GtiocbPin, Gpt4, "gpt4"); // GTIOC4B_A
+impl_gpt_pin!("port1", port1, 1, GtiocaPin, Gpt5, "gpt5"); // GTIOC5A_A
+impl_gpt_pin!("port1", port1, 0, GtiocbPin, Gpt5, "gpt5"); // GTIOC5B_A
+impl_gpt_pin!("port4", port4, 0, GtiocaPin, Gpt6, "gpt6"); // GTIOC6A_A
+impl_gpt_pin!("port4", port4, 1, GtiocbPin, Gpt6, "gpt6"); // GTIOC6B_A
+impl_gpt_pin!("port3", port3, 4, GtiocaPin, Gpt7, "gpt7"); // GTIOC7A_A
+impl_gpt_pin!("port3", port3, 3, GtiocbPin, Gpt7, "gpt7"); // GTIOC7B_A
+impl_gpt_pin!("port1", port1, 7, GtiocaPin, Gpt8, "gpt8"); // GTIOC8A_A
+impl_gpt_pin!("port1", port1, 6, GtiocbPin, Gpt8, "gpt8"); // GTIOC8B_A
+impl_gpt_pin!("port4", port4, 11, GtiocaPin, Gpt9, "gpt9"); // GTIOC9A_A
+impl_gpt_pin!("port4", port4, 10, GtiocbPin, Gpt9, "gpt9"); // GTIOC9B_A
+
+/// Count clock divider selected by GTCR.TPCS = 0-5: 1, 4, 16, 64, 256, 1024.
+pub(crate) fn prescaler_divider(tpcs: u8) -> u32 {
+    1 << (2 * tpcs)
+}
+
+/// Division of PCLKD for the count clock (GTCR.TPCS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prescaler {
+    Div1 = 0,
+    Div4 = 1,
+    Div16 = 2,
+    Div64 = 3,
+    Div256 = 4,
+    Div1024 = 5,
+}
+
+/// Picks the smallest prescaler (GTCR.TPCS), for the finest resolution, at
+/// which `ticks(count_hz)` counts fit into 1..=`max`. `ticks` maps the count
+/// clock frequency to the number of counts needed at it. Returns the setting
+/// and the count.
+pub(crate) fn prescaler_setting(pclkd: u32, max: u32, ticks: impl Fn(u32) -> u64) -> Option<(u8, u32)> {
+    (0..6u8).find_map(|tpcs| {
+        let counts = ticks(pclkd / prescaler_divider(tpcs));
+        (counts >= 1 && counts <= max as u64).then_some((tpcs, counts as u32))
+    })
+}
+
+/// ELC link feeding the GPT hardware trigger inputs (ELC_GPTA-ELC_GPTH).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElcTrigger {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+    E = 4,
+    F = 5,
+    G = 6,
+    H = 7,
+}
+
+/// Routes ICU/ELC event number `event` (e.g. `Gpt0::OVF_EVENT`) to GPT
+/// trigger input `trigger`, for `TriggerSource::Elc`.
+pub fn route_elc_event(elc: &mut ra4m2_pac::Elc, trigger: ElcTrigger, event: u16) {
+    cortex_m::interrupt::free(|cs| {
+        power::enable_elc(cs);
+    });
+    unsafe {
+        // ELSR0-7 are the GPT links
+        elc.elsr().get(trigger as usize).modify(|w| w.els().set(event.into()));
+        elc.elcr().modify(|w| w.elcon().set(Elcon::_1));
+    }
+}
+
+/// Hardware start, stop or clear source of a channel (GTSSR/GTPSR/GTCSR).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerSource {
+    /// Rising edge of the group's GTETRG pin, muxed to `PinFunction::GPTA`.
+    GtetrgRising(poeg::Group),
+    /// Falling edge of the group's GTETRG pin.
+    GtetrgFalling(poeg::Group),
+    /// An event routed with `route_elc_event`.
+    Elc(ElcTrigger),
+}
+
+impl TriggerSource {
+    pub(crate) fn bit(self) -> u32 {
+        match self {
+            TriggerSource::GtetrgRising(group) => 1 << (2 * group as u32),
+            TriggerSource::GtetrgFalling(group) => 1 << (2 * group as u32 + 1),
+            TriggerSource::Elc(trigger) => 1 << (16 + trigger as u32),
+        }
+    }
+}
+
+/// Bits of the hardware sources in GTSSR/GTPSR/GTCSR, below the software
+/// enable in bit 31.
+const HARDWARE_SOURCES: u32 = 0x7FFF_FFFF;
+
+fn source_bits(sources: &[TriggerSource]) -> u32 {
+    sources.iter().fold(0, |bits, source| bits | source.bit())
+}
+
+/// A set of GPT channels that start, stop and clear together.
+///
+/// Channels are added from their tokens, so only channels the caller owns
+/// can join; build the set before handing the tokens to their drivers, and
+/// set hardware sources after configuring the drivers, as those reset them.
+/// Software start, stop and clear (GTSSR.CSTRT, GTPSR.CSTOP, GTCSR.CCLR)
+/// are re-enabled on each call, after any driver setup.
+#[derive(Clone, Copy)]
+pub struct ChannelSet {
+    /// Register block accessors by channel number.
+    channels: [Option<fn() -> GptRegs>; 10],
+}
+
+impl ChannelSet {
+    pub const fn new() -> Self {
+        ChannelSet { channels: [None; 10] }
+    }
+
+    /// Adds the channel of `channel`, releasing it from module stop.
+    pub fn with<T: Instance>(mut self, _channel: &T) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+        self.channels[T::CHANNEL as usize] = Some(T::regs);
+        self
+    }
+
+    /// Bit n set for channel n.
+    pub fn mask(&self) -> u32 {
+        self.channels.iter().enumerate().filter(|(_, regs)| regs.is_some()).fold(0, |mask, (n, _)| mask | 1 << n)
+    }
+
+    fn regs(&self) -> impl Iterator<Item = GptRegs> + '_ {
+        self.channels.iter().flatten().map(|regs| regs())
+    }
+
+    // GTSTR, GTSTP and GTCLR act on every channel whose bit is written as 1,
+    // whichever channel's copy is written, provided the channel allows
+    // software start, stop and clear
+    fn write_common(&self, write: impl Fn(&GptRegs, u32)) {
+        for regs in self.regs() {
+            unsafe {
+                regs.gtssr().modify(|w| w.cstrt().set(Cstrt::_1));
+                regs.gtpsr().modify(|w| w.cstop().set(Cstop::_1));
+                regs.gtcsr().modify(|w| w.cclr().set(Cclr::_1));
+            }
+        }
+        if let Some(regs) = self.regs().next() {
+            write(&regs, self.mask());
+        }
+    }
+
+    /// Starts all counters in the same cycle.
+    pub fn start(&self) {
+        self.write_common(|regs, mask| unsafe { regs.gtstr().init(|w| w.set_raw(mask)) });
+    }
+
+    /// Stops all counters in the same cycle.
+    pub fn stop(&self) {
+        self.write_common(|regs, mask| unsafe { regs.gtstp().init(|w| w.set_raw(mask)) });
+    }
+
+    /// Clears all counters in the same cycle: to 0 when counting up, to GTPR
+    /// when counting down.
+    pub fn clear(&self) {
+        self.write_common(|regs, mask| unsafe { regs.gtclr().init(|w| w.set_raw(mask)) });
+    }
+
+    /// Starts every channel in the set on any of `sources`, replacing their
+    /// previous hardware start sources.
+    pub fn start_on(&self, sources: &[TriggerSource]) {
+        let bits = source_bits(sources);
+        for regs in self.regs() {
+            unsafe {
+                regs.gtssr().modify(|w| w.set_raw(w.get_raw() & !HARDWARE_SOURCES | bits));
+            }
+        }
+    }
+
+    /// Stops every channel in the set on any of `sources`, replacing their
+    /// previous hardware stop sources.
+    pub fn stop_on(&self, sources: &[TriggerSource]) {
+        let bits = source_bits(sources);
+        for regs in self.regs() {
+            unsafe {
+                regs.gtpsr().modify(|w| w.set_raw(w.get_raw() & !HARDWARE_SOURCES | bits));
+            }
+        }
+    }
+
+    /// Clears every channel in the set on any of `sources`, replacing their
+    /// previous hardware clear sources.
+    pub fn clear_on(&self, sources: &[TriggerSource]) {
+        let bits = source_bits(sources);
+        for regs in self.regs() {
+            unsafe {
+                regs.gtcsr().modify(|w| w.set_raw(w.get_raw() & !HARDWARE_SOURCES | bits));
+            }
+        }
+    }
+
+    /// Reads the counters, one channel after the other.
+    pub fn counts(&self) -> [Option<u32>; 10] {
+        self.channels.map(|regs| regs.map(|regs| unsafe { regs().gtcnt().read().get() }))
+    }
+}
+
+impl Default for ChannelSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,9 @@
-use ra4m2_pac::{iic0::{iccr1::{Ice, Iicrst}, iccr2::{Sp, St}, icmr1::{Bc, Bcwp}, icmr3::{Ackbt, Ackwp, Wait}, icser::Sar0E, icsr2::{Nackf, Stop}}, Iic0, RegisterValue};
-use crate::power;
+use ra4m2_pac::{iic0::{iccr1::{Ice, Iicrst}, iccr2::{Rs, Sp, St}, icfer::Tmoe, icmr1::{Bc, Bcwp}, icmr2::{Tmoh, Tmol, Tmos}, icmr3::{Ackbt, Ackwp, Smbs, Wait}, icser::Sar0E, icsr2::{Nackf, Start, Stop}}, Iic0 as IicRegs, RegisterValue};
+
+use embassy_time::Duration;
+use embedded_hal::i2c::Operation;
+
+use crate::{gpio::{AlternateFunction, Output, PinFunction}, power, sealed, timeout::Deadline};
 
 enum Direction {
     Write = 0x00,
@@ -22,6 +26,8 @@ pub enum I2cError {
     PeripheralNotStopped,
     TransmitBufferNotReady,
     DataNotReceived,
+    /// The hardware timeout detector saw SCL held for too long (SMBus mode).
+    BusTimeout,
 }
 
 
@@ -33,6 +39,29 @@ impl embedded_hal::i2c::Error for I2cError {
             I2cError::PeripheralNotStopped => embedded_hal::i2c::ErrorKind::Other,
             I2cError::TransmitBufferNotReady => embedded_hal::i2c::ErrorKind::Other,
             I2cError::DataNotReceived => embedded_hal::i2c::ErrorKind::NoAcknowledge(embedded_hal::i2c::NoAcknowledgeSource::Data),
+            I2cError::BusTimeout => embedded_hal::i2c::ErrorKind::Bus,
+        }
+    }
+}
+
+/// I2C driver configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2cConfig {
+    /// How long each step of a transfer (bus free, buffer ready, byte
+    /// received, stop condition, ...) may take before it is reported as an
+    /// error. Measured in wall-clock time, so it is independent of ICLK.
+    pub timeout: Duration,
+    /// Puts the IIC block in SMBus mode: SMBus data hold time and hardware
+    /// detection of SCL stuck low/high (reported as `I2cError::BusTimeout`).
+    /// Host Notify from devices is not received.
+    pub smbus: bool,
+}
+
+impl Default for I2cConfig {
+    fn default() -> Self {
+        I2cConfig {
+            timeout: Duration::from_millis(10),
+            smbus: false,
         }
     }
 }
@@ -42,340 +71,646 @@ fn get_slave_address(address: u8, direction: Direction) -> u8 {
     (address << 1) | direction as u8
 }
 
-/// Trait for I2C SDA pin
-pub trait I2cSDAPin {}
-/// Trait for I2C SCL pin
-pub trait I2cSCLPin {}
+/// Marks a pin that can carry the SDA signal of IIC channel `T`. Pins must
+/// be muxed to `T::PIN_FUNCTION` with `into_alternate_function` first.
+pub trait I2cSDAPin<T: Instance> {}
+/// Marks a pin that can carry the SCL signal of IIC channel `T`. Pins must
+/// be muxed to `T::PIN_FUNCTION` with `into_alternate_function` first.
+pub trait I2cSCLPin<T: Instance> {}
+
+/// An IIC channel. Implemented by `Iic0` and `Iic1`; sealed so drivers can
+/// rely on the register layout and event numbers.
+pub trait Instance: sealed::Sealed {
+    /// ICU event number for receive data full (RXI).
+    const RXI_EVENT: u16;
+    /// ICU event number for transmit data empty (TXI).
+    const TXI_EVENT: u16;
+    /// ICU event number for transmit end (TEI).
+    const TEI_EVENT: u16;
+    /// ICU event number for communication error / event (EEI).
+    const EEI_EVENT: u16;
+    /// Peripheral function the channel's SDA and SCL pins are muxed to.
+    const PIN_FUNCTION: PinFunction;
+
+    /// Register block of this channel.
+    fn regs() -> IicRegs;
+
+    /// Releases the channel from module stop.
+    fn enable_power(cs: &cortex_m::interrupt::CriticalSection);
+}
 
-macro_rules! define_i2c {
-    ($name:ident, $IIC:ident, $power_func:ident) => {
-        /// I2C (Inter-Integrated Circuit) driver for RA4M2 microcontroller
-        #[repr(align(4))]
+macro_rules! iic_instance {
+    ($feature:literal, $name:ident, $regs:ident, $power_func:ident, $rxi:literal, $txi:literal, $tei:literal, $eei:literal) => {
+        /// Owned IIC channel, created from its PAC token and handing it back
+        /// from `free`.
+        #[cfg(feature = $feature)]
         pub struct $name {
-            iic: $IIC,
+            iic: IicRegs,
         }
 
+        #[cfg(feature = $feature)]
         impl $name {
-            /// Creates a new I2C instance with the given IIC0 peripheral.
-            pub fn new(iic: $IIC) -> Self {
-                cortex_m::interrupt::free(|cs| {
-                    power::$power_func(cs); 
-                });
-
+            /// Both IIC channels share one PAC register-block type, so the
+            /// token is checked against the channel's address at run time.
+            /// Panics on another channel's token.
+            pub fn new(iic: IicRegs) -> Self {
+                assert!(iic == ra4m2_pac::$regs, "PAC token of another IIC channel");
                 $name { iic }
             }
 
-            /// Initializes the I2C settings for the given slave address.
-            fn initialize_settings(&mut self, address: u8) {
-                // https://www.renesas.com/en/document/man/ra4m2-group-users-manual-hardware?r=1469026
-                // See page 1004 of the RA4M2 manual for initialization flowchart
-                unsafe {
-                    self.iic.iccr1().modify(|w| w.ice().set(Ice::_0));
-                    self.iic.iccr1().modify(|w| w.iicrst().set(Iicrst::_1));
-                    self.iic.iccr1().modify(|w| w.ice().set(Ice::_1));
-                    
-                    // TODO: We are just writing to the IIC register for slave address 0, 7 bit only
-                    self.iic.sarl().get(0).modify(|w| w.sva().set(address));
-                    self.iic.saru().get(0).modify(|w| w.sva().set(0));
-                    self.iic.icser().modify(|w| w.sar0e().set(Sar0E::_1));
-
-                    // Not sure icmr1, 2, 3 need to be set for basic operations, bit counter defaults to 0?
-                    self.iic.icmr1().modify(|w| w.bcwp().set(Bcwp::_1).bc().set(Bc::_000));
-
-                    self.iic.iccr1().modify(|w| w.iicrst().set(Iicrst::_0));
-                }
+            /// Returns the PAC token.
+            pub fn free(self) -> IicRegs {
+                self.iic
             }
+        }
 
-            fn is_bus_free(&self) -> bool {
-                unsafe {
-                    self.iic.iccr2().read().bbsy().get().0 == 0
-                }
-            }
+        #[cfg(feature = $feature)]
+        impl sealed::Sealed for $name {}
 
-            fn wait_for_bus(&self) -> Result<(), I2cError> {
-                let mut timeout = 0;
-                while !self.is_bus_free() {
-                    if timeout > 10000 {
-                        return Err(I2cError::BusBusy);
-                    }
-                    timeout += 1;
-                }
-                Ok(())
-            }
+        #[cfg(feature = $feature)]
+        impl Instance for $name {
+            const RXI_EVENT: u16 = $rxi;
+            const TXI_EVENT: u16 = $txi;
+            const TEI_EVENT: u16 = $tei;
+            const EEI_EVENT: u16 = $eei;
+            const PIN_FUNCTION: PinFunction = PinFunction::IIC;
 
-            fn start(&mut self) {
-                unsafe {
-                    self.iic.iccr2().modify(|w| w.st().set(St::_1));
-                }
+            fn regs() -> IicRegs {
+                ra4m2_pac::$regs
             }
 
-            fn stop(&mut self) {
-                unsafe {
-                    self.iic.iccr2().modify(|w| w.sp().set(Sp::_1));
-                }
+            fn enable_power(cs: &cortex_m::interrupt::CriticalSection) {
+                power::$power_func(cs);
             }
+        }
+    };
+}
 
-            fn unstop(&mut self) {
-                unsafe {
-                    self.iic.iccr2().modify(|w| w.sp().set(Sp::_0));
-                }
-            }
+iic_instance!("iic0", Iic0, IIC0, enable_i2c0, 0x05B, 0x05C, 0x05D, 0x05E);
+iic_instance!("iic1", Iic1, IIC1, enable_i2c1, 0x060, 0x061, 0x062, 0x063);
 
-            fn wait_for_stop(&self) -> Result<(), I2cError> {
-                unsafe{ 
-                    let mut timeout = 0;
-                    while self.iic.icsr2().read().stop().get().0 == 0 {
-                        if timeout > 10000 {
-                            return Err(I2cError::PeripheralNotStopped);
-                        }
-                        timeout += 1;
-                    }
-                }
-                Ok(())
-            }
+macro_rules! impl_i2c_pin {
+    ($feature:literal, $port:ident, $n:literal, $pin_trait:ident, $instance:ident, $instance_feature:literal) => {
+        #[cfg(all(feature = $feature, feature = $instance_feature))]
+        impl $pin_trait<$instance> for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {}
+    };
+}
 
-            fn clear_stop_flag(&mut self) {
-                unsafe {
-                    self.iic.icsr2().modify(|w| w.stop().set(Stop::_0));
-                }
-            }
+// Pin assignments from the "Peripheral Select Settings" tables of the
+// RA4M2 User's Manual.
+impl_i2c_pin!("port4", port4, 0, I2cSCLPin, Iic0, "iic0"); // SCL0_A
+impl_i2c_pin!("port4", port4, 1, I2cSDAPin, Iic0, "iic0"); // SDA0_A
+impl_i2c_pin!("port4", port4, 8, I2cSCLPin, Iic0, "iic0"); // SCL0_B
+impl_i2c_pin!("port4", port4, 7, I2cSDAPin, Iic0, "iic0"); // SDA0_B
+impl_i2c_pin!("port2", port2, 5, I2cSCLPin, Iic1, "iic1"); // SCL1_A
+impl_i2c_pin!("port2", port2, 6, I2cSDAPin, Iic1, "iic1"); // SDA1_A
+impl_i2c_pin!("port1", port1, 0, I2cSCLPin, Iic1, "iic1"); // SCL1_B
+impl_i2c_pin!("port1", port1, 1, I2cSDAPin, Iic1, "iic1"); // SDA1_B
+
+/// I2C (Inter-Integrated Circuit) master driver for RA4M2 microcontroller,
+/// generic over the IIC channel.
+///
+/// The SDA and SCL pins are mutably borrowed for `'d`, so they can't be
+/// reconfigured while the driver owns the bus. `free` hands the channel and
+/// the pins back.
+#[repr(align(4))]
+pub struct I2c<'d, T: Instance, SDA: I2cSDAPin<T>, SCL: I2cSCLPin<T>> {
+    iic: T,
+    sda: &'d mut SDA,
+    scl: &'d mut SCL,
+    config: I2cConfig,
+}
 
-            fn transmit_buffer_ready(&self) -> bool {
-                unsafe {
-                    self.iic.icsr2().read().tdre().get().0 == 1
-                }
-            }
+/// I2C driver on IIC0.
+#[cfg(feature = "iic0")]
+pub type I2c0<'d, SDA, SCL> = I2c<'d, Iic0, SDA, SCL>;
+
+/// I2C driver on IIC1.
+#[cfg(feature = "iic1")]
+pub type I2c1<'d, SDA, SCL> = I2c<'d, Iic1, SDA, SCL>;
+
+impl<'d, T: Instance, SDA: I2cSDAPin<T>, SCL: I2cSCLPin<T>> I2c<'d, T, SDA, SCL> {
+    /// Creates a new I2C driver on channel `iic` using the given pins.
+    pub fn new(
+        iic: T,
+        sda: &'d mut SDA,
+        scl: &'d mut SCL,
+        config: I2cConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        I2c { iic, sda, scl, config }
+    }
 
-            fn wait_transmit_buffer_ready(&self) -> Result<(), I2cError> {
-                let mut timeout = 0;
-                while !self.transmit_buffer_ready() {
-                    if timeout > 10000 {
-                        return Err(I2cError::TransmitBufferNotReady);
-                    }
-                    timeout += 1;
-                }
-                Ok(())
-            }
+    /// Releases the channel, whose own `free` returns the PAC token, and
+    /// the pins.
+    pub fn free(self) -> (T, &'d mut SDA, &'d mut SCL) {
+        (self.iic, self.sda, self.scl)
+    }
 
-            fn is_data_received(&self) -> bool {
-                unsafe {
-                    self.iic.icsr2().read().rdrf().get().0 == 1
-                }
+    /// Busy-waits until `condition` holds, failing with `error` once the
+    /// configured timeout has elapsed.
+    fn wait_for(&self, error: I2cError, condition: impl Fn(&Self) -> bool) -> Result<(), I2cError> {
+        let deadline = Deadline::after(self.config.timeout);
+        while !condition(self) {
+            if self.bus_timed_out() {
+                return Err(I2cError::BusTimeout);
             }
-
-            fn wait_data_received(&self) -> Result<(), I2cError> {
-                let mut timeout = 0;
-                while !self.is_data_received() {
-                    if timeout > 10000 {
-                        return Err(I2cError::DataNotReceived);
-                    }
-                    timeout += 1;
-                }
-                Ok(())
+            if deadline.expired() {
+                return Err(error);
             }
+        }
+        Ok(())
+    }
 
-            fn slave_acknowledged(&self) -> bool {
-                unsafe {
-                    self.iic.icsr2().read().nackf().get().0 == 0
-                }
-            }
+    /// Whether the SMBus timeout detector has fired. Always `false`
+    /// unless `I2cConfig::smbus` is set; cleared by the IIC reset at
+    /// the start of the next transfer.
+    fn bus_timed_out(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().tmof().get().0 == 1
+        }
+    }
 
-            fn clear_nack_flag(&mut self) {
-                unsafe {
-                    self.iic.icsr2().modify(|w| w.nackf().set(Nackf::_0));
-                }
+    /// Initializes the I2C settings for the given slave address.
+    fn initialize_settings(&mut self, address: u8) {
+        // https://www.renesas.com/en/document/man/ra4m2-group-users-manual-hardware?r=1469026
+        // See page 1004 of the RA4M2 manual for initialization flowchart
+        unsafe {
+            T::regs().iccr1().modify(|w| w.ice().set(Ice::_0));
+            T::regs().iccr1().modify(|w| w.iicrst().set(Iicrst::_1));
+            T::regs().iccr1().modify(|w| w.ice().set(Ice::_1));
+            
+            // TODO: We are just writing to the IIC register for slave address 0, 7 bit only
+            T::regs().sarl().get(0).modify(|w| w.sva().set(address));
+            T::regs().saru().get(0).modify(|w| w.sva().set(0));
+            T::regs().icser().modify(|w| w.sar0e().set(Sar0E::_1));
+
+            // Not sure icmr1, 2, 3 need to be set for basic operations, bit counter defaults to 0?
+            T::regs().icmr1().modify(|w| w.bcwp().set(Bcwp::_1).bc().set(Bc::_000));
+
+            if self.config.smbus {
+                // The IIC reset above clears these, so they are reapplied
+                // for every transfer. Long timeout mode, counting while SCL
+                // is held either low or high.
+                T::regs().icmr3().modify(|w| w.smbs().set(Smbs::_1));
+                T::regs().icmr2().modify(|w| w.tmos().set(Tmos::_0).tmol().set(Tmol::_1).tmoh().set(Tmoh::_1));
+                T::regs().icfer().modify(|w| w.tmoe().set(Tmoe::_1));
             }
 
-            fn transmit_complete(&self) -> bool {
-                unsafe {
-                    self.iic.icsr2().read().tend().get().0 == 1
-                }
-            }
+            T::regs().iccr1().modify(|w| w.iicrst().set(Iicrst::_0));
+        }
+    }
 
-            fn wait_transmit_complete(&self) -> Result<(), I2cError> {
-                let mut timeout = 0;
-                while !self.transmit_complete() {
-                    if timeout > 10000 {
-                        return Err(I2cError::SlaveNotResponding);
-                    }
-                    timeout += 1;
-                }
-                Ok(())
-            }
+    fn is_bus_free(&self) -> bool {
+        unsafe {
+            T::regs().iccr2().read().bbsy().get().0 == 0
+        }
+    }
 
-            fn set_wait(&mut self) {
-                unsafe {
-                    self.iic.icmr3().modify(|w| w.wait().set(Wait::_1));
-                }
-            }
+    fn wait_for_bus(&self) -> Result<(), I2cError> {
+        self.wait_for(I2cError::BusBusy, Self::is_bus_free)
+    }
 
-            fn clear_wait(&mut self) {
-                unsafe {
-                    self.iic.icmr3().modify(|w| w.wait().set(Wait::_0));
-                }
+    fn start(&mut self) {
+        unsafe {
+            T::regs().iccr2().modify(|w| w.st().set(St::_1));
+        }
+    }
+
+    fn stop(&mut self) {
+        unsafe {
+            T::regs().iccr2().modify(|w| w.sp().set(Sp::_1));
+        }
+    }
+
+    fn unstop(&mut self) {
+        unsafe {
+            T::regs().iccr2().modify(|w| w.sp().set(Sp::_0));
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().stop().get().0 == 1
+        }
+    }
+
+    fn wait_for_stop(&self) -> Result<(), I2cError> {
+        self.wait_for(I2cError::PeripheralNotStopped, Self::is_stopped)
+    }
+
+    fn clear_stop_flag(&mut self) {
+        unsafe {
+            T::regs().icsr2().modify(|w| w.stop().set(Stop::_0));
+        }
+    }
+
+    fn transmit_buffer_ready(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().tdre().get().0 == 1
+        }
+    }
+
+    fn wait_transmit_buffer_ready(&self) -> Result<(), I2cError> {
+        self.wait_for(I2cError::TransmitBufferNotReady, Self::transmit_buffer_ready)
+    }
+
+    fn is_data_received(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().rdrf().get().0 == 1
+        }
+    }
+
+    fn wait_data_received(&self) -> Result<(), I2cError> {
+        self.wait_for(I2cError::DataNotReceived, Self::is_data_received)
+    }
+
+    fn slave_acknowledged(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().nackf().get().0 == 0
+        }
+    }
+
+    fn clear_nack_flag(&mut self) {
+        unsafe {
+            T::regs().icsr2().modify(|w| w.nackf().set(Nackf::_0));
+        }
+    }
+
+    fn transmit_complete(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().tend().get().0 == 1
+        }
+    }
+
+    fn wait_transmit_complete(&self) -> Result<(), I2cError> {
+        self.wait_for(I2cError::SlaveNotResponding, Self::transmit_complete)
+    }
+
+    fn set_wait(&mut self) {
+        unsafe {
+            T::regs().icmr3().modify(|w| w.wait().set(Wait::_1));
+        }
+    }
+
+    fn clear_wait(&mut self) {
+        unsafe {
+            T::regs().icmr3().modify(|w| w.wait().set(Wait::_0));
+        }
+    }
+
+    fn add_byte_to_transmit(&mut self, byte: u8) {
+        unsafe {
+            T::regs().icdrt().modify(|w| w.set_raw(byte));
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        unsafe {
+            T::regs().icdrr().read().get_raw()
+        }
+    }
+
+    fn acknowledge_slave(&mut self) {
+        unsafe {
+            // Turn off write protect first
+            T::regs().icmr3().modify(|w| w.ackwp().set(Ackwp::_1));
+            // Acknowledge the slave
+            T::regs().icmr3().modify(|w| w.ackbt().set(Ackbt::_1));
+        }
+    }
+
+    /// Sends only the address byte (write direction) and reports whether
+    /// a device acknowledged it. No data is transferred, so devices see
+    /// a start, their address and a stop.
+    fn address_only(&mut self, address: u8) -> Result<bool, I2cError> {
+        self.initialize_settings(address);
+
+        self.wait_for_bus()?;
+
+        self.start();
+
+        self.wait_transmit_buffer_ready()?;
+
+        self.add_byte_to_transmit(get_slave_address(address, Direction::Write));
+
+        // Either the address is acknowledged (TEND) or NACKF is raised
+        let result = self.wait_for(I2cError::SlaveNotResponding, |s| {
+            s.transmit_complete() || !s.slave_acknowledged()
+        });
+        let acknowledged = self.slave_acknowledged();
+
+        self.clear_stop_flag();
+
+        self.stop();
+
+        self.wait_for_stop()?;
+
+        self.clear_nack_flag();
+
+        self.unstop();
+
+        result?;
+
+        Ok(acknowledged)
+    }
+
+    /// Returns `true` if a device acknowledges `address`. Issues an
+    /// address-only write, which has no side effects on devices.
+    pub fn probe(&mut self, address: u8) -> Result<bool, I2cError> {
+        self.address_only(address)
+    }
+
+    /// Probes every non-reserved 7-bit address (0x08..=0x77) and yields
+    /// the ones that acknowledge. Bus errors on an address are treated
+    /// as "no device".
+    pub fn scan(&mut self) -> impl Iterator<Item = u8> + '_ {
+        (0x08..0x78).filter(move |&address| self.probe(address).unwrap_or(false))
+    }
+
+    fn is_started(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().start().get().0 == 1
+        }
+    }
+
+    /// Asks for a repeated start (ICCR2.RS) on the bus this transfer holds,
+    /// for the next operation of the transaction.
+    fn request_restart(&mut self) {
+        unsafe {
+            T::regs().icsr2().modify(|w| w.start().set(Start::_0));
+            T::regs().iccr2().modify(|w| w.rs().set(Rs::_1));
+        }
+    }
+
+    /// Issues a start condition on a free bus or, with `repeated`, waits for
+    /// the repeated start requested at the end of the previous operation.
+    fn begin(&mut self, address: u8, repeated: bool) -> Result<(), I2cError> {
+        if repeated {
+            self.wait_for(I2cError::BusBusy, Self::is_started)?;
+            unsafe {
+                T::regs().icsr2().modify(|w| w.start().set(Start::_0));
             }
+        } else {
+            self.initialize_settings(address);
+            self.wait_for_bus()?;
+            self.start();
+        }
+        Ok(())
+    }
 
-            fn add_byte_to_transmit(&mut self, byte: u8) {
-                unsafe {
-                    self.iic.icdrt().modify(|w| w.set_raw(byte));
-                }
+    /// Sends the address byte and `data`. With `stop` the transfer ends with
+    /// a stop condition, otherwise with a repeated start request. Errors
+    /// always end it with a stop.
+    fn write_transfer(&mut self, address: u8, data: impl Iterator<Item = u8>, repeated: bool, stop: bool) -> Result<(), I2cError> {
+        self.begin(address, repeated)?;
+
+        let mut acknowledgement_issue = false;
+        let mut buffer_timeout = false;
+        let mut bytes = core::iter::once(get_slave_address(address, Direction::Write)).chain(data);
+        let mut next = bytes.next();
+        let mut deadline = Deadline::after(self.config.timeout);
+
+        while let Some(byte) = next {
+            if deadline.expired() {
+                buffer_timeout = true;
+                break;
             }
 
-            fn read_byte(&mut self) -> u8 {
-                unsafe {
-                    self.iic.icdrr().read().get_raw()
+            if self.slave_acknowledged() {
+                if self.transmit_buffer_ready() {
+                    self.add_byte_to_transmit(byte);
+                    next = bytes.next();
+                    deadline = Deadline::after(self.config.timeout);
                 }
+            }else{
+                acknowledgement_issue = true;
+                break;
             }
+        }
 
-            fn acknowledge_slave(&mut self) {
-                unsafe {
-                    // Turn off write protect first
-                    self.iic.icmr3().modify(|w| w.ackwp().set(Ackwp::_1));
-                    // Acknowledge the slave
-                    self.iic.icmr3().modify(|w| w.ackbt().set(Ackbt::_1));
-                }
+        let mut completion_error = None;
+        if acknowledgement_issue == false && buffer_timeout == false {
+            // The last byte is either acknowledged (TEND) or NACKF is raised
+            match self.wait_for(I2cError::SlaveNotResponding, |s| {
+                s.transmit_complete() || !s.slave_acknowledged()
+            }) {
+                Ok(()) => acknowledgement_issue = !self.slave_acknowledged(),
+                Err(error) => completion_error = Some(error),
             }
+        }
 
-            /// Writes data to the I2C slave device at the specified address.
-            pub fn write(&mut self, address: u8, data: &[u8]) -> Result<(), I2cError> {
-                self.initialize_settings(address);
-
-                self.wait_for_bus()?;
-
-                self.start();
-                
-                let mut acknowledgement_issue = false;
-                let bytes_to_transmit = data.len() + 1; // +1 for the address byte
-                let mut bytes_transmitted = 0;
-
-                while bytes_transmitted < bytes_to_transmit {
-                    if self.slave_acknowledged() {
-                        if self.transmit_buffer_ready() {
-                            // transmit data
-                            match bytes_transmitted {
-                                0 => {
-                                    self.add_byte_to_transmit(get_slave_address(address, Direction::Write));
-                                }
-                                _ => {
-                                    self.add_byte_to_transmit(data[bytes_transmitted - 1]);
-                                }
-                            }
-                            bytes_transmitted += 1;
-                        }
-                    }else{
-                        acknowledgement_issue = true;
-                        break;
-                    }
-                }
+        if stop || acknowledgement_issue || buffer_timeout || completion_error.is_some() {
+            self.clear_stop_flag();
 
-                if acknowledgement_issue == false {
-                    self.wait_transmit_complete()?;
-                }
+            self.stop();
 
-                self.clear_stop_flag();
-                
-                self.stop();
-                
-                self.wait_for_stop()?;
+            self.wait_for_stop()?;
 
-                self.clear_nack_flag();
+            self.clear_nack_flag();
 
-                self.unstop();
+            self.unstop();
+        } else {
+            self.request_restart();
+        }
 
+        if acknowledgement_issue {
+            return Err(I2cError::SlaveNotResponding);
+        }
 
-                if acknowledgement_issue {
-                    return Err(I2cError::SlaveNotResponding);
-                }
+        if buffer_timeout {
+            return Err(I2cError::TransmitBufferNotReady);
+        }
 
-                Ok(())
-            }
+        if let Some(error) = completion_error {
+            return Err(error);
+        }
 
-            pub fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
-                self.initialize_settings(address);
+        Ok(())
+    }
 
-                self.wait_for_bus()?;
+    /// Receives `len` bytes (at least one) into `bytes`, NACKing the last.
+    /// With `counted`, the first byte is a count and the transfer is cut to
+    /// the count byte, that many bytes and `counted` more, within 2 to `len`.
+    /// With `stop` the transfer ends with a stop condition, otherwise with a
+    /// repeated start request.
+    fn read_transfer<'b>(&mut self, address: u8, mut len: usize, mut bytes: impl Iterator<Item = &'b mut u8>, counted: Option<usize>, repeated: bool, stop: bool) -> Result<(), I2cError> {
+        self.begin(address, repeated)?;
 
-                self.start();
+        self.wait_transmit_buffer_ready()?;
 
-                self.wait_transmit_buffer_ready()?;
+        self.add_byte_to_transmit(get_slave_address(address, Direction::Read));
 
-                self.add_byte_to_transmit(get_slave_address(address, Direction::Read));
+        self.wait_data_received()?;
 
-                self.wait_data_received()?;
+        if self.slave_acknowledged() == false {
+            self.clear_stop_flag();
+            self.stop();
+            self.read_byte(); // dummy read
+            self.wait_for_stop()?;
+            self.clear_nack_flag();
+            self.clear_stop_flag();
+            return Err(I2cError::SlaveNotResponding);
+        }
 
-                if self.slave_acknowledged() == false {
-                    self.clear_stop_flag();
-                    self.stop();
-                    self.read_byte(); // dummy read
-                    self.wait_for_stop()?;
-                    self.clear_nack_flag();
-                    self.clear_stop_flag();
-                    return Err(I2cError::SlaveNotResponding);
-                }
+        self.read_byte(); // dummy read 
 
-                self.read_byte(); // dummy read 
+        if counted.is_some() {
+            // Until the count byte is in, it may turn out to be the second
+            // to last byte
+            self.set_wait();
+        }
 
-                for i in 0..buffer.len() {
-                    self.wait_data_received()?;
+        let mut last = None;
+        let mut i = 0;
+        while i < len {
+            self.wait_data_received()?;
+
+            let slot = bytes.next();
+            if i == len - 1 {
+                // Last byte has some extra stuff to do
+                last = slot;
+                break;
+            }else if i == len - 2 {
+                // Second to last byte, we need to acknowledge the slave
+                self.set_wait();
+            }
 
-                    if i == buffer.len() - 1 {
-                        // Last byte has some extra stuff to do
-                        break;
-                    }else if i == buffer.len() - 2 {
-                        // Second to last byte, we need to acknowledge the slave
-                        self.set_wait();
-                    }
+            let byte = self.read_byte();
+            if let Some(slot) = slot {
+                *slot = byte;
+            }
 
-                    buffer[i] = self.read_byte();
+            if let (0, Some(trailing)) = (i, counted) {
+                len = (1 + byte as usize + trailing).clamp(2, len);
+                if len > 2 {
+                    self.clear_wait();
                 }
+            }
+            i += 1;
+        }
 
-                self.acknowledge_slave();
+        self.acknowledge_slave();
 
-                buffer[buffer.len() - 1] = self.read_byte();
+        let byte = self.read_byte();
+        if let Some(slot) = last {
+            *slot = byte;
+        }
 
-                self.wait_data_received()?;
+        self.wait_data_received()?;
 
-                self.clear_stop_flag();
+        if stop {
+            self.clear_stop_flag();
 
-                self.stop();
+            self.stop();
 
-                self.read_byte(); // dummy read
+            self.read_byte(); // dummy read
 
-                self.clear_wait();
+            self.clear_wait();
 
-                self.wait_for_stop()?;
+            self.wait_for_stop()?;
 
-                self.clear_nack_flag();
+            self.clear_nack_flag();
 
-                self.clear_stop_flag();
+            self.clear_stop_flag();
+        } else {
+            // SCL is held by WAIT; the dummy read releases it into the
+            // repeated start
+            self.request_restart();
 
-                Ok(())
-            }
-        }
+            self.read_byte(); // dummy read
 
-        impl embedded_hal::i2c::ErrorType for $name {
-            type Error = I2cError;
+            self.clear_wait();
         }
 
-        impl embedded_hal::i2c::I2c<embedded_hal::i2c::SevenBitAddress> for $name {
-            fn transaction(&mut self, address: u8, operations: &mut [embedded_hal::i2c::Operation<'_>]) -> Result<(), Self::Error> {
-                for operation in operations {
-                    match operation {
-                        embedded_hal::i2c::Operation::Write(write) => {
-                            self.write(address, write)?;
-                        }
-                        embedded_hal::i2c::Operation::Read(read) => {
-                            self.read(address, read)?;
-                        }
-                    }
-                }
-                Ok(())
+        Ok(())
+    }
+
+    /// Runs the operations as one transaction: a start, a repeated start
+    /// between operations of different direction, and a single stop at the
+    /// end. Adjacent operations of the same direction are merged into one
+    /// transfer, as `embedded_hal::i2c::I2c::transaction` requires.
+    fn run(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), I2cError> {
+        let mut first = 0;
+        while first < operations.len() {
+            let is_read = matches!(operations[first], Operation::Read(_));
+            let end = first + operations[first..]
+                .iter()
+                .take_while(|operation| matches!(operation, Operation::Read(_)) == is_read)
+                .count();
+            let repeated = first > 0;
+            let stop = end == operations.len();
+            let group = &mut operations[first..end];
+
+            if is_read {
+                let len: usize = group
+                    .iter()
+                    .map(|operation| match operation {
+                        Operation::Read(buffer) => buffer.len(),
+                        Operation::Write(_) => 0,
+                    })
+                    .sum();
+                // The master has to clock at least one byte after the
+                // address in the read direction; receive one and discard it.
+                let mut discard = 0u8;
+                let bytes = group
+                    .iter_mut()
+                    .flat_map(|operation| match operation {
+                        Operation::Read(buffer) => buffer.iter_mut(),
+                        Operation::Write(_) => <&mut [u8]>::default().iter_mut(),
+                    })
+                    .chain((len == 0).then_some(&mut discard));
+                self.read_transfer(address, len.max(1), bytes, None, repeated, stop)?;
+            } else {
+                let bytes = group.iter().flat_map(|operation| match operation {
+                    Operation::Write(data) => data.iter().copied(),
+                    Operation::Read(_) => [].iter().copied(),
+                });
+                self.write_transfer(address, bytes, repeated, stop)?;
             }
+
+            first = end;
         }
+        Ok(())
+    }
+
+    /// Writes data to the I2C slave device at the specified address.
+    /// An empty `data` sends just the address byte.
+    pub fn write(&mut self, address: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.run(address, &mut [Operation::Write(data)])
+    }
+
+    /// Reads `buffer.len()` bytes from the I2C slave device at the
+    /// specified address.
+    pub fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.run(address, &mut [Operation::Read(buffer)])
+    }
+
+    /// Writes `write`, then after a repeated start reads a count byte into
+    /// `frame[0]` followed by exactly that many bytes and `trailing` more
+    /// (e.g. a PEC byte), NACKing the last. This is the read half of an
+    /// SMBus block read. Returns the count byte; if the block doesn't fit in
+    /// `frame` the read is cut short there. A count of 0 with no `trailing`
+    /// bytes still clocks one extra byte, as the count byte can't be NACKed
+    /// before it is known. Panics if `frame` is shorter than 2 bytes.
+    pub fn write_read_counted(&mut self, address: u8, write: &[u8], frame: &mut [u8], trailing: usize) -> Result<usize, I2cError> {
+        assert!(frame.len() >= 2, "counted read frame shorter than 2 bytes");
+        self.write_transfer(address, write.iter().copied(), false, false)?;
+        let len = frame.len();
+        self.read_transfer(address, len, frame.iter_mut(), Some(trailing), true, true)?;
+        Ok(frame[0] as usize)
     }
 }
-#[cfg(feature = "iic0")]
-define_i2c!(I2c0, Iic0, enable_i2c0);
 
-#[cfg(feature = "iic1")]   
-define_i2c!(I2c1, Iic1, enable_i2c1);
+impl<'d, T: Instance, SDA: I2cSDAPin<T>, SCL: I2cSCLPin<T>> embedded_hal::i2c::ErrorType for I2c<'d, T, SDA, SCL> {
+    type Error = I2cError;
+}
+
+impl<'d, T: Instance, SDA: I2cSDAPin<T>, SCL: I2cSCLPin<T>> embedded_hal::i2c::I2c<embedded_hal::i2c::SevenBitAddress> for I2c<'d, T, SDA, SCL> {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        self.run(address, operations)
+    }
+}
@@ -0,0 +1,297 @@
+//! I2C target (slave) mode on the IIC channels, plus IIC0's address-match
+//! wakeup from software standby.
+//!
+//! The target answers a single 7-bit own address through slave address
+//! register 0. `listen` waits for the host to address us and reports the
+//! direction; `respond_to_write` / `respond_to_read` then move the data until
+//! the host ends the transfer with a stop condition.
+
+use core::marker::PhantomData;
+
+use ra4m2_pac::{iic0::{iccr1::{Ice, Iicrst}, icmr3::{Ackbt, Ackwp}, icser::Sar0E, icsr1::Aas0, icsr2::{Nackf, Stop}}, iic0wu::{icwur::{Wuack, Wuafa, Wue, Wuf, Wuie}, icwur2::Wusen}, RegisterValue};
+
+#[cfg(feature = "iic0")]
+use crate::i2c::Iic0;
+use crate::{i2c::{I2cConfig, I2cError, I2cSCLPin, I2cSDAPin, Instance}, icu, timeout::Deadline};
+
+/// ICU event number of the IIC0 wakeup interrupt (WUI).
+pub const IIC0_WUI_EVENT: u16 = 0x05F;
+
+/// Transfer direction requested by the host, from the host's point of view.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TargetEvent {
+    /// The host writes to us; follow with `respond_to_write`.
+    Write,
+    /// The host reads from us; follow with `respond_to_read`.
+    Read,
+}
+
+/// I2C target driver, generic over the IIC channel. Pins are borrowed for
+/// `'d` the same way as for the master driver.
+pub struct I2cTarget<'d, T: Instance> {
+    iic: T,
+    config: I2cConfig,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> I2cTarget<'d, T> {
+    /// Creates a target answering to the 7-bit `address`.
+    pub fn new<SDA: I2cSDAPin<T>, SCL: I2cSCLPin<T>>(
+        iic: T,
+        _sda: &'d mut SDA,
+        _scl: &'d mut SCL,
+        address: u8,
+        config: I2cConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        // Same reset sequence as the master driver, see page 1004 of the
+        // RA4M2 manual. Slave address 0 holds our own address.
+        unsafe {
+            T::regs().iccr1().modify(|w| w.ice().set(Ice::_0));
+            T::regs().iccr1().modify(|w| w.iicrst().set(Iicrst::_1));
+            T::regs().iccr1().modify(|w| w.ice().set(Ice::_1));
+
+            T::regs().sarl().get(0).modify(|w| w.sva().set(address));
+            T::regs().saru().get(0).modify(|w| w.sva().set(0));
+            T::regs().icser().modify(|w| w.sar0e().set(Sar0E::_1));
+
+            T::regs().iccr1().modify(|w| w.iicrst().set(Iicrst::_0));
+        }
+
+        I2cTarget { iic, config, _pins: PhantomData }
+    }
+
+    /// Releases the channel.
+    pub fn free(self) -> T {
+        self.iic
+    }
+
+    fn wait_for(&self, error: I2cError, condition: impl Fn(&Self) -> bool) -> Result<(), I2cError> {
+        let deadline = Deadline::after(self.config.timeout);
+        while !condition(self) {
+            if deadline.expired() {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn addressed(&self) -> bool {
+        unsafe {
+            T::regs().icsr1().read().aas0().get().0 == 1
+        }
+    }
+
+    fn is_transmit_mode(&self) -> bool {
+        unsafe {
+            T::regs().iccr2().read().trs().get().0 == 1
+        }
+    }
+
+    fn is_data_received(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().rdrf().get().0 == 1
+        }
+    }
+
+    fn transmit_buffer_ready(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().tdre().get().0 == 1
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().stop().get().0 == 1
+        }
+    }
+
+    fn host_nacked(&self) -> bool {
+        unsafe {
+            T::regs().icsr2().read().nackf().get().0 == 1
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        unsafe {
+            T::regs().icdrr().read().get_raw()
+        }
+    }
+
+    fn add_byte_to_transmit(&mut self, byte: u8) {
+        unsafe {
+            T::regs().icdrt().modify(|w| w.set_raw(byte));
+        }
+    }
+
+    /// Sets whether the next received byte is acknowledged (`true`) or NACKed.
+    fn set_ack(&mut self, ack: bool) {
+        let ackbt = if ack { Ackbt::_0 } else { Ackbt::_1 };
+        unsafe {
+            T::regs().icmr3().modify(|w| w.ackwp().set(Ackwp::_1));
+            T::regs().icmr3().modify(|w| w.ackbt().set(ackbt));
+        }
+    }
+
+    fn finish(&mut self) {
+        unsafe {
+            T::regs().icsr1().modify(|w| w.aas0().set(Aas0::_0));
+            T::regs().icsr2().modify(|w| w.nackf().set(Nackf::_0).stop().set(Stop::_0));
+        }
+        self.set_ack(true);
+    }
+
+    /// Waits for the host to address us, then reports which direction it
+    /// wants. Returns `BusTimeout` if no address match arrives within the
+    /// configured timeout; call again to keep listening.
+    pub fn listen(&mut self) -> Result<TargetEvent, I2cError> {
+        self.wait_for(I2cError::BusTimeout, Self::addressed)?;
+
+        if self.is_transmit_mode() {
+            Ok(TargetEvent::Read)
+        } else {
+            Ok(TargetEvent::Write)
+        }
+    }
+
+    /// Receives the bytes the host writes after `listen` returned
+    /// `TargetEvent::Write`, until the stop condition. Bytes beyond
+    /// `buffer.len()` are NACKed. Returns the number of bytes stored.
+    pub fn respond_to_write(&mut self, buffer: &mut [u8]) -> Result<usize, I2cError> {
+        self.set_ack(!buffer.is_empty());
+
+        // The first RDRF after the address match holds our own address
+        self.wait_for(I2cError::DataNotReceived, |s| s.is_data_received())?;
+        self.read_byte();
+
+        let mut count = 0;
+        loop {
+            self.wait_for(I2cError::DataNotReceived, |s| s.is_data_received() || s.is_stopped())?;
+            if !self.is_data_received() {
+                break;
+            }
+
+            let byte = self.read_byte();
+            if count < buffer.len() {
+                buffer[count] = byte;
+                count += 1;
+                if count == buffer.len() {
+                    self.set_ack(false);
+                }
+            }
+        }
+
+        self.finish();
+        Ok(count)
+    }
+
+    /// Transmits `data` while the host reads after `listen` returned
+    /// `TargetEvent::Read`. Sends 0xFF if the host keeps reading past the end.
+    /// Returns the number of bytes of `data` the host read.
+    pub fn respond_to_read(&mut self, data: &[u8]) -> Result<usize, I2cError> {
+        let mut sent = 0;
+        loop {
+            self.wait_for(I2cError::TransmitBufferNotReady, |s| s.transmit_buffer_ready() || s.host_nacked())?;
+            if self.host_nacked() {
+                break;
+            }
+
+            let byte = data.get(sent).copied().unwrap_or(0xFF);
+            self.add_byte_to_transmit(byte);
+            sent += 1;
+        }
+
+        // The host NACKs the last byte it wants; the byte already queued
+        // after it is never sent. Release SCL with a dummy read.
+        self.read_byte();
+        self.wait_for(I2cError::PeripheralNotStopped, Self::is_stopped)?;
+
+        self.finish();
+        Ok(sent.saturating_sub(1).min(data.len()))
+    }
+}
+
+/// Address-match wakeup, only available on IIC0.
+///
+/// While armed, IIC0 keeps comparing addresses from the SCL/SDA lines even
+/// with PCLKB stopped in software standby. When the host addresses us the
+/// ICU wakes the chip and, if enabled in `IELSR`, the WUI event
+/// (`IIC0_WUI_EVENT`) fires. After waking, call `wakeup_occurred` and then
+/// `listen` as usual to serve the transfer that woke us.
+#[cfg(feature = "iic0")]
+impl<'d> I2cTarget<'d, Iic0> {
+    /// Arms IIC0 to wake the chip from software standby when our address is
+    /// matched. The IIC0WU token is only borrowed to show the caller owns it.
+    /// Returns `BusTimeout` if the switch to asynchronous matching doesn't
+    /// complete within the configured timeout.
+    pub fn enable_wakeup(&mut self, _wakeup: &mut ra4m2_pac::Iic0Wu) -> Result<(), I2cError> {
+        unsafe {
+            // Acknowledge the matching address so the host's transfer
+            // carries on once the chip is awake.
+            ra4m2_pac::IIC0WU.icwur().modify(|w| {
+                w.wuafa().set(Wuafa::_0)
+                    .wuack().set(Wuack::_1)
+                    .wuie().set(Wuie::_1)
+                    .wue().set(Wue::_1)
+            });
+
+            // Switch address matching to the asynchronous (SCL clocked) path,
+            // which keeps running without PCLKB.
+            ra4m2_pac::IIC0WU.icwur2().modify(|w| w.wusen().set(Wusen::_0));
+        }
+        self.wait_for(I2cError::BusTimeout, |_| Self::is_asynchronous())?;
+
+        icu::set_iic0_wakeup(true);
+        Ok(())
+    }
+
+    /// Disarms the wakeup and returns IIC0 to normal synchronous operation.
+    /// Returns `BusTimeout` if the switch back doesn't complete within the
+    /// configured timeout; call again to retry.
+    pub fn disable_wakeup(&mut self, _wakeup: &mut ra4m2_pac::Iic0Wu) -> Result<(), I2cError> {
+        icu::set_iic0_wakeup(false);
+
+        self.switch_to_synchronous()?;
+        unsafe {
+            ra4m2_pac::IIC0WU.icwur().modify(|w| w.wuie().set(Wuie::_0).wue().set(Wue::_0));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` (and clears the flag) if an address match woke the
+    /// chip. Call from the WUI handler or right after leaving standby; the
+    /// channel is switched back to synchronous operation so `listen` works.
+    /// Returns `BusTimeout` if that switch doesn't complete within the
+    /// configured timeout; the flag is left set so the call can be retried.
+    pub fn wakeup_occurred(&mut self, _wakeup: &mut ra4m2_pac::Iic0Wu) -> Result<bool, I2cError> {
+        unsafe {
+            if ra4m2_pac::IIC0WU.icwur().read().wuf().get().0 == 0 {
+                return Ok(false);
+            }
+        }
+
+        self.switch_to_synchronous()?;
+        unsafe {
+            ra4m2_pac::IIC0WU.icwur().modify(|w| w.wuf().set(Wuf::_0));
+        }
+        Ok(true)
+    }
+
+    fn is_asynchronous() -> bool {
+        unsafe {
+            ra4m2_pac::IIC0WU.icwur2().read().wuasyf().get().0 == 1
+        }
+    }
+
+    fn switch_to_synchronous(&self) -> Result<(), I2cError> {
+        unsafe {
+            ra4m2_pac::IIC0WU.icwur2().modify(|w| w.wusen().set(Wusen::_1));
+        }
+        self.wait_for(I2cError::BusTimeout, |_| unsafe {
+            ra4m2_pac::IIC0WU.icwur2().read().wusyf().get().0 == 1
+        })
+    }
+}
@@ -1,12 +1,14 @@
 use core::{cell::RefCell, panic};
 
 use cortex_m::interrupt::InterruptNumber;
-use ra4m2_pac::NoBitfieldReg;
+use ra4m2_pac::{icu::wupen::Iic0Wupen, NoBitfieldReg};
 
 static ICU: cortex_m::interrupt::Mutex<RefCell<Option<ra4m2_pac::Icu>>> = cortex_m::interrupt::Mutex::new(RefCell::new(None));
 
 static CLEAR_INTERRUPT: u32 = 0xFFFE_FFFF; // Mask to clear the interrupt
 
+static DTC_ENABLE: u32 = 1 << 24; // IELSRn.DTCE
+
 static INTERRUPT_EVENTS: u16 = 96; // Example event number for interrupts
 
 /// Interrupt Control Unit (ICU) structure
@@ -59,3 +61,38 @@ pub fn clear_interrupt<T: InterruptNumber>(interrupt: T) {
     }
 }
 
+
+/// Enables or disables IIC0 address match as a source for returning from
+/// software standby (WUPEN.IIC0WUPEN).
+pub fn set_iic0_wakeup(enable: bool) {
+    let value = if enable { Iic0Wupen::_1 } else { Iic0Wupen::_0 };
+    cortex_m::interrupt::free(|cs| {
+        if let Some(icu) = ICU.borrow(cs).borrow_mut().as_mut() {
+            unsafe {
+                icu.wupen().modify(|w| w.iic0wupen().set(value));
+                let _ = icu.wupen().read();
+                cortex_m::asm::dsb();
+            }
+        }
+    });
+}
+
+/// Routes a registered interrupt's event to the DTC (IELSRn.DTCE) so each
+/// event starts the transfer described by DTC vector `n` instead of only
+/// interrupting the CPU.
+pub fn enable_dtc<T: InterruptNumber>(interrupt: T) {
+    if interrupt.number() < INTERRUPT_EVENTS {
+        cortex_m::interrupt::free(|cs| {
+            if let Some(icu) = ICU.borrow(cs).borrow_mut().as_mut() {
+                unsafe {
+                    let contents = icu.ielsr().get(interrupt.number() as usize).read().get();
+                    icu.ielsr().get(interrupt.number() as usize).modify(|w| w.set(contents | DTC_ENABLE));
+                    let _ = icu.ielsr().get(interrupt.number() as usize).read();
+                    cortex_m::asm::dsb();
+                }
+            }
+        });
+    } else {
+        panic!("Event number out of range");
+    }
+}
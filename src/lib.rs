@@ -5,13 +5,40 @@ use ra4m2_pac::Peripherals;
 use crate::sysc::SystemClock;
 
 pub mod sysc;
+pub mod capture;
+pub mod encoder;
 pub mod gpio;
+pub mod gpt;
 pub mod i2c;
+pub mod i2c_target;
+pub mod smbus;
+pub mod poeg;
 pub mod power;
+pub mod one_shot;
+pub mod pwm;
+pub mod three_phase;
 pub mod time_driver;
 pub mod icu;
 pub mod pfsel;
+#[cfg(feature = "qspi")]
+pub mod qspi;
+#[cfg(feature = "qspi")]
+pub mod qspi_flash;
+pub mod sci;
+pub mod sci_i2c;
+pub mod sci_spi;
+pub mod smart_card;
+pub mod spi;
+#[cfg(feature = "spi0")]
+pub mod spi_slave;
+#[cfg(feature = "spi0")]
+pub mod async_spi;
+pub mod timeout;
+pub mod uart;
+pub mod async_uart;
+pub mod dtc;
 mod port_map;
+mod waker;
 
 mod sealed {
     pub trait Sealed {}
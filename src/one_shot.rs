@@ -0,0 +1,218 @@
+//! Single pulses with a programmable delay and width, on a GPT channel.
+//!
+//! The channel runs in saw-wave one-shot mode: once started, the counter
+//! counts up from 0 to GTPR and stops by itself at the end of the cycle,
+//! back at 0. The output goes active at the compare match `delay` counts
+//! after the start and inactive at the cycle end, `width` counts later, so
+//! the pulse is timed entirely by hardware.
+//!
+//! The start comes from software (`trigger`) or from the hardware sources
+//! given to `arm` (GTSSR): a GTETRG pin edge or an event routed through the
+//! ELC, see `gpt::route_elc_event`. Triggers arriving while a pulse is in
+//! progress are ignored. The hardware sources are synchronised to PCLKD, which
+//! adds a latency of a few PCLKD cycles to the delay.
+
+use core::marker::PhantomData;
+
+use ra4m2_pac::{gpt320::{gtcr::Cst, gtior::{Oadflt, Oae, Obdflt, Obe}, gtuddtyc::{Ud, Udf}}, NoBitfieldReg, RegisterValue};
+
+use crate::{gpt::{self, GtiocaPin, GtiocbPin, Instance, TriggerSource, GTCCRA, GTCCRB}, pwm::Polarity, sysc::SystemClock};
+
+/// Saw-wave one-shot pulse mode (GTCR.MD).
+pub(crate) const MD_ONE_SHOT: u8 = 0b001;
+
+// GTIOR.GTIOA/GTIOB, as in `pwm`: inactive at the start, active at the
+// compare match, inactive again at the cycle end.
+const ACTIVE_HIGH: u8 = 0b0_10_01;
+const ACTIVE_LOW: u8 = 0b1_01_10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OneShotConfig {
+    /// Time from the trigger to the start of the pulse in nanoseconds,
+    /// rounded to whole counts and at least one count.
+    pub delay_ns: u32,
+    /// Pulse width in nanoseconds, rounded to whole counts and at least one
+    /// count.
+    pub width_ns: u32,
+    pub polarity: Polarity,
+}
+
+impl Default for OneShotConfig {
+    fn default() -> Self {
+        OneShotConfig {
+            delay_ns: 1_000,
+            width_ns: 1_000,
+            polarity: Polarity::ActiveHigh,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    A,
+    B,
+}
+
+/// Counts for `ns` nanoseconds at `count_hz`, rounded to the nearest count.
+fn ns_to_counts(ns: u32, count_hz: u32) -> u64 {
+    (ns as u64 * count_hz as u64 + 500_000_000) / 1_000_000_000
+}
+
+/// GTCCRA/GTCCRB and GTPR for a pulse `width` counts long, `delay` counts
+/// after the start.
+fn compare_and_period(delay: u64, width: u64) -> (u64, u64) {
+    let delay = delay.max(1);
+    (delay, delay + width.max(1) - 1)
+}
+
+/// One-shot pulse driver on GPT channel `T`. The output pin is borrowed for
+/// `'d` the same way as for `i2c::I2c`, and sits at its inactive level
+/// between pulses.
+pub struct OneShot<'d, T: Instance> {
+    gpt: T,
+    output: Output,
+    /// Count clock frequency in Hz.
+    count_hz: u32,
+    _pin: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> OneShot<'d, T> {
+    /// Produces pulses on GTIOCnA. Panics if the delay and width together
+    /// don't fit the counter at any prescaler.
+    pub fn new_a<P: GtiocaPin<T>>(gpt: T, _pin: &'d mut P, system_clock: &SystemClock, config: OneShotConfig) -> Self {
+        Self::init(gpt, Output::A, system_clock, config)
+    }
+
+    /// Produces pulses on GTIOCnB. Panics if the delay and width together
+    /// don't fit the counter at any prescaler.
+    pub fn new_b<P: GtiocbPin<T>>(gpt: T, _pin: &'d mut P, system_clock: &SystemClock, config: OneShotConfig) -> Self {
+        Self::init(gpt, Output::B, system_clock, config)
+    }
+
+    fn init(gpt: T, output: Output, system_clock: &SystemClock, config: OneShotConfig) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        let pclkd = system_clock.get_pclkd_freq();
+        let (tpcs, _) = gpt::prescaler_setting(pclkd, T::MAX_COUNT, |count_hz| {
+            let (_, gtpr) = compare_and_period(ns_to_counts(config.delay_ns, count_hz), ns_to_counts(config.width_ns, count_hz));
+            gtpr
+        })
+        .expect("one-shot delay and width out of range for PCLKD");
+
+        let one_shot = OneShot {
+            gpt,
+            output,
+            count_hz: pclkd / gpt::prescaler_divider(tpcs),
+            _pin: PhantomData,
+        };
+
+        let gtio = match config.polarity {
+            Polarity::ActiveHigh => ACTIVE_HIGH,
+            Polarity::ActiveLow => ACTIVE_LOW,
+        };
+        // Level while stopped: the inactive one
+        let inactive_high = config.polarity == Polarity::ActiveLow;
+
+        unsafe {
+            let regs = T::regs();
+            regs.gtcr().modify(|w| w.set_raw(0));
+            regs.gtcr().modify(|w| w.md().set(MD_ONE_SHOT.into()).tpcs().set(tpcs.into()));
+            regs.gtuddtyc().modify(|w| w.set_raw(0).ud().set(Ud::_1).udf().set(Udf::_0));
+            // No buffering: the compare value and period are only written
+            // between pulses
+            regs.gtber().modify(|w| w.set_raw(0));
+            regs.gtcnt().modify(|w| w.set(0));
+            regs.gtssr().modify(|w| w.set_raw(0));
+            regs.gtpsr().modify(|w| w.set_raw(0));
+            regs.gtcsr().modify(|w| w.set_raw(0));
+            regs.gtst().modify(|w| w.set_raw(0));
+            regs.gtior().modify(|w| match output {
+                Output::A => {
+                    let oadflt = if inactive_high { Oadflt::_1 } else { Oadflt::_0 };
+                    w.set_raw(0).gtioa().set(gtio.into()).oadflt().set(oadflt).oae().set(Oae::_1)
+                }
+                Output::B => {
+                    let obdflt = if inactive_high { Obdflt::_1 } else { Obdflt::_0 };
+                    w.set_raw(0).gtiob().set(gtio.into()).obdflt().set(obdflt).obe().set(Obe::_1)
+                }
+            });
+        }
+        let (compare, gtpr) = one_shot.counts(config.delay_ns, config.width_ns).expect("one-shot delay and width out of range for PCLKD");
+        one_shot.write_timing(compare, gtpr);
+
+        one_shot
+    }
+
+    /// Stops any pulse in progress, removes the hardware triggers and
+    /// releases the channel.
+    pub fn free(mut self) -> T {
+        self.disarm();
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_0));
+            T::regs().gtior().modify(|w| w.set_raw(0));
+        }
+        self.gpt
+    }
+
+    /// Count clock frequency in Hz; its period is the timing resolution.
+    pub fn tick_hz(&self) -> u32 {
+        self.count_hz
+    }
+
+    /// Compare value and GTPR at the current prescaler, if they fit.
+    fn counts(&self, delay_ns: u32, width_ns: u32) -> Option<(u32, u32)> {
+        let (compare, gtpr) = compare_and_period(ns_to_counts(delay_ns, self.count_hz), ns_to_counts(width_ns, self.count_hz));
+        (gtpr <= T::MAX_COUNT as u64).then_some((compare as u32, gtpr as u32))
+    }
+
+    fn write_timing(&self, compare: u32, gtpr: u32) {
+        let index = match self.output {
+            Output::A => GTCCRA,
+            Output::B => GTCCRB,
+        };
+        unsafe {
+            let regs = T::regs();
+            regs.gtccr().get(index).modify(|w| w.set(compare));
+            regs.gtpr().modify(|w| w.set(gtpr));
+        }
+    }
+
+    /// Changes the delay and width, keeping the prescaler. Waits for a pulse
+    /// in progress to end first. Panics if they don't fit the counter at the
+    /// current prescaler.
+    pub fn set_timing(&mut self, delay_ns: u32, width_ns: u32) {
+        let (compare, gtpr) = self.counts(delay_ns, width_ns).expect("one-shot delay and width out of range for the prescaler");
+        while self.is_busy() {}
+        self.write_timing(compare, gtpr);
+    }
+
+    /// Starts a pulse on any of `sources`, replacing the previous hardware
+    /// triggers.
+    pub fn arm(&mut self, sources: &[TriggerSource]) {
+        let bits = sources.iter().fold(0, |bits, source| bits | source.bit());
+        unsafe {
+            T::regs().gtssr().modify(|w| w.set_raw(bits));
+        }
+    }
+
+    /// Removes the hardware triggers. A pulse in progress still completes.
+    pub fn disarm(&mut self) {
+        unsafe {
+            T::regs().gtssr().modify(|w| w.set_raw(0));
+        }
+    }
+
+    /// Starts a pulse from software, unless one is in progress.
+    pub fn trigger(&mut self) {
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_1));
+        }
+    }
+
+    /// A pulse, or the delay before it, is in progress.
+    pub fn is_busy(&self) -> bool {
+        unsafe { T::regs().gtcr().read().cst().get().0 == 1 }
+    }
+}
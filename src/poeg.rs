@@ -0,0 +1,302 @@
+//! Port Output Enable for GPT (POEG): hardware shutdown of GPT outputs.
+//!
+//! Each of the four groups (A-D) collects disable requests from its GTETRG
+//! input pin, the analog comparators (ACMPHS0-5), main oscillator stop, GPT
+//! output short detection (both outputs of a channel at the same active
+//! level) and software. A request immediately forces the outputs of every
+//! GPT channel linked to the group to their disabled level, with no software
+//! involved; `status` and the group's interrupt then report the cause.
+//!
+//! The request sources (PIDE, IOCE, OSTPE, CDREn) and the pin's polarity and
+//! noise filter can only be written once after reset, so each group is
+//! configured in a single call.
+//!
+//! The application routes a group's POEG event to an IELSR slot of its
+//! choice with `enable_interrupt` and forwards that interrupt to
+//! `Poeg::on_interrupt`.
+
+use core::{future::poll_fn, marker::PhantomData, task::Poll};
+
+use ra4m2_pac::{gpt320::gtintad::{Grpabh, Grpabl}, Interrupt, RegisterValue};
+
+use crate::{gpio::{AlternateFunction, Output}, gpt, icu, power, waker::WakerSlot};
+
+/// ICU event numbers for the group A-D disable requests (POEG0-3_EVENT).
+const EVENTS: [u16; 4] = [0x070, 0x071, 0x072, 0x073];
+
+// POEGGn bits
+const PIDF: u32 = 1 << 0;
+const IOCF: u32 = 1 << 1;
+const OSTPF: u32 = 1 << 2;
+const SSF: u32 = 1 << 3;
+const PIDE: u32 = 1 << 4;
+const IOCE: u32 = 1 << 5;
+const OSTPE: u32 = 1 << 6;
+const CDRE_SHIFT: u32 = 8;
+const ST: u32 = 1 << 16;
+const INV: u32 = 1 << 28;
+const NFEN: u32 = 1 << 29;
+const NFCS_SHIFT: u32 = 30;
+const FLAGS: u32 = PIDF | IOCF | OSTPF | SSF;
+
+static WAKERS: [WakerSlot; 4] = [WakerSlot::new(), WakerSlot::new(), WakerSlot::new(), WakerSlot::new()];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    A = 0,
+    B = 1,
+    C = 2,
+    D = 3,
+}
+
+/// Request sources of a group, other than its GTETRG pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoegConfig {
+    /// GPT output short detection on the linked channels (see `GptLink`).
+    pub output_short: bool,
+    /// Main clock oscillation stop.
+    pub oscillation_stop: bool,
+    /// Bit n enables comparator ACMPHSn's output as a request.
+    pub comparators: u8,
+}
+
+/// Sampling clock of the GTETRG noise filter, which requires three equal
+/// samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseFilter {
+    Pclkb = 0,
+    PclkbDiv8 = 1,
+    PclkbDiv32 = 2,
+    PclkbDiv128 = 3,
+}
+
+/// GTETRG pin request settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinInput {
+    /// The request is raised while the pin is low rather than high.
+    pub active_low: bool,
+    pub noise_filter: Option<NoiseFilter>,
+}
+
+/// Level a GPT output takes while its group disables it (GTIOR.OADF/OBDF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisabledLevel {
+    /// Not disabled by POEG.
+    Unaffected = 0,
+    HighImpedance = 1,
+    Low = 2,
+    High = 3,
+}
+
+/// How a GPT channel responds to its POEG group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GptLink {
+    pub output_a: DisabledLevel,
+    pub output_b: DisabledLevel,
+    /// Request a disable when GTIOCnA and GTIOCnB are both high.
+    pub short_high: bool,
+    /// Request a disable when GTIOCnA and GTIOCnB are both low.
+    pub short_low: bool,
+}
+
+/// Why a group's outputs are disabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DisableCause {
+    /// GTETRG pin input (PIDF).
+    pub pin: bool,
+    /// GPT output short, or a comparator (IOCF).
+    pub gpt_or_comparator: bool,
+    /// Main clock oscillation stop (OSTPF).
+    pub oscillation_stop: bool,
+    /// `Poeg::disable` (SSF).
+    pub software: bool,
+}
+
+impl DisableCause {
+    fn from_bits(bits: u32) -> Self {
+        DisableCause {
+            pin: bits & PIDF != 0,
+            gpt_or_comparator: bits & IOCF != 0,
+            oscillation_stop: bits & OSTPF != 0,
+            software: bits & SSF != 0,
+        }
+    }
+
+    /// Any request is latched.
+    pub fn any(&self) -> bool {
+        self.pin || self.gpt_or_comparator || self.oscillation_stop || self.software
+    }
+}
+
+/// The request is still active, so the outputs can't be re-enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StillActive;
+
+/// Marks a pin that can carry GTETRG of a POEG group. Pins must be muxed to
+/// `PinFunction::GPTA` first.
+pub trait GtetrgPin {
+    const GROUP: Group;
+}
+
+macro_rules! impl_gtetrg_pin {
+    ($feature:literal, $port:ident, $n:literal, $group:ident) => {
+        #[cfg(feature = $feature)]
+        impl GtetrgPin for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {
+            const GROUP: Group = Group::$group;
+        }
+    };
+}
+
+// Pin assignments from the "Peripheral Select Settings" tables of the
+// RA4M2 User's Manual.
+impl_gtetrg_pin!("port1", port1, 5, A); // GTETRGA_B
+impl_gtetrg_pin!("port1", port1, 4, B); // GTETRGB_B
+impl_gtetrg_pin!("port4", port4, 15, A); // GTETRGA_A
+impl_gtetrg_pin!("port4", port4, 14, B); // GTETRGB_A
+
+/// POEG driver for all four groups. GTETRG pins are borrowed for `'d` the
+/// same way as for `i2c::I2c`.
+pub struct Poeg<'d> {
+    poeg: ra4m2_pac::Poeg,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d> Poeg<'d> {
+    pub fn new(poeg: ra4m2_pac::Poeg) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            power::enable_poeg(cs);
+        });
+
+        Poeg { poeg, _pins: PhantomData }
+    }
+
+    pub fn free(self) -> ra4m2_pac::Poeg {
+        self.poeg
+    }
+
+    fn read(&self, group: Group) -> u32 {
+        unsafe {
+            match group {
+                Group::A => self.poeg.poegga().read().get_raw(),
+                Group::B => self.poeg.poeggb().read().get_raw(),
+                Group::C => self.poeg.poeggc().read().get_raw(),
+                Group::D => self.poeg.poeggd().read().get_raw(),
+            }
+        }
+    }
+
+    // Flags are cleared by writing 0 and unaffected by writing 1, except SSF,
+    // which is set by writing 1
+    fn write(&mut self, group: Group, value: u32) {
+        unsafe {
+            match group {
+                Group::A => self.poeg.poegga().init(|w| w.set_raw(value)),
+                Group::B => self.poeg.poeggb().init(|w| w.set_raw(value)),
+                Group::C => self.poeg.poeggc().init(|w| w.set_raw(value)),
+                Group::D => self.poeg.poeggd().init(|w| w.set_raw(value)),
+            }
+        }
+    }
+
+    fn configure_group(&mut self, group: Group, config: PoegConfig, pin: Option<PinInput>) {
+        let mut value = PIDF | IOCF | OSTPF | (config.comparators as u32 & 0x3F) << CDRE_SHIFT;
+        if config.output_short || config.comparators != 0 {
+            value |= IOCE;
+        }
+        if config.oscillation_stop {
+            value |= OSTPE;
+        }
+        if let Some(pin) = pin {
+            value |= PIDE;
+            if pin.active_low {
+                value |= INV;
+            }
+            if let Some(filter) = pin.noise_filter {
+                value |= NFEN | (filter as u32) << NFCS_SHIFT;
+            }
+        }
+        self.write(group, value);
+    }
+
+    /// Enables `group`'s request sources. Only the first configuration of a
+    /// group after reset takes effect.
+    pub fn configure(&mut self, group: Group, config: PoegConfig) {
+        self.configure_group(group, config, None);
+    }
+
+    /// Like `configure`, also enabling requests from the GTETRG pin of the
+    /// pin's group.
+    pub fn configure_with_pin<P: GtetrgPin>(&mut self, _pin: &'d mut P, config: PoegConfig, input: PinInput) {
+        self.configure_group(P::GROUP, config, Some(input));
+    }
+
+    /// Links GPT channel `T` to `group`. Call after creating the channel's
+    /// driver, which resets GTIOR.
+    pub fn link<T: gpt::Instance>(&mut self, group: Group, link: GptLink) {
+        let grpabh = if link.short_high { Grpabh::_1 } else { Grpabh::_0 };
+        let grpabl = if link.short_low { Grpabl::_1 } else { Grpabl::_0 };
+        unsafe {
+            let regs = T::regs();
+            regs.gtintad().modify(|w| w.grp().set((group as u8).into()).grpabh().set(grpabh).grpabl().set(grpabl));
+            regs.gtior().modify(|w| {
+                w.oadf().set((link.output_a as u8).into()).obdf().set((link.output_b as u8).into())
+            });
+        }
+    }
+
+    /// Requests that are latched for `group`.
+    pub fn status(&self, group: Group) -> DisableCause {
+        DisableCause::from_bits(self.read(group))
+    }
+
+    /// `group`'s GTETRG input is at its active level, after the noise filter.
+    pub fn pin_active(&self, group: Group) -> bool {
+        self.read(group) & ST != 0
+    }
+
+    /// Disables `group`'s outputs from software.
+    pub fn disable(&mut self, group: Group) {
+        let value = self.read(group) | PIDF | IOCF | OSTPF | SSF;
+        self.write(group, value);
+    }
+
+    /// Clears `group`'s latched requests, re-enabling its outputs, unless the
+    /// GTETRG pin is still active. A source that is still asserting will
+    /// latch its request again.
+    pub fn release(&mut self, group: Group) -> Result<(), StillActive> {
+        let value = self.read(group);
+        if value & PIDE != 0 && value & ST != 0 {
+            return Err(StillActive);
+        }
+        self.write(group, value & !FLAGS);
+        Ok(())
+    }
+
+    /// Routes `group`'s disable request to `interrupt`.
+    pub fn enable_interrupt(&mut self, group: Group, interrupt: Interrupt) {
+        icu::register_interrupt(interrupt, EVENTS[group as usize]);
+    }
+
+    /// Interrupt handler for any group's disable request.
+    pub fn on_interrupt(interrupt: Interrupt) {
+        icu::clear_interrupt(interrupt);
+        for waker in &WAKERS {
+            waker.wake();
+        }
+    }
+
+    /// Waits for `group` to latch a request. Needs `enable_interrupt` for
+    /// the group.
+    pub async fn wait(&mut self, group: Group) -> DisableCause {
+        poll_fn(|cx| {
+            WAKERS[group as usize].register(cx.waker());
+            let cause = self.status(group);
+            if cause.any() {
+                Poll::Ready(cause)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
@@ -1,6 +1,6 @@
 use core::cell::RefCell;
 
-use ra4m2_pac::{mstp::{mstpcrb::{Mstpb8, Mstpb9}, mstpcrd::Mstpd3}, Mstp};
+use ra4m2_pac::{mstp::{mstpcrb::{Mstpb6, Mstpb8, Mstpb9, Mstpb19, Mstpb22, Mstpb27, Mstpb28, Mstpb29, Mstpb30, Mstpb31}, mstpcrc::Mstpc14, mstpcrd::{Mstpd3, Mstpd14}}, Mstp, RegisterValue};
 
 static POWER: cortex_m::interrupt::Mutex<RefCell<Option<Mstp>>> = cortex_m::interrupt::Mutex::new(RefCell::new(None));
 
@@ -38,6 +38,140 @@ pub fn enable_i2c1(cs: &cortex_m::interrupt::CriticalSection) {
     }
 }
 
+/// Enables the power management system for the SCI0 module
+pub fn enable_sci0(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable SCI0 module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb31().set(Mstpb31::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the SCI1 module
+pub fn enable_sci1(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable SCI1 module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb30().set(Mstpb30::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the SCI2 module
+pub fn enable_sci2(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable SCI2 module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb29().set(Mstpb29::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the SCI3 module
+pub fn enable_sci3(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable SCI3 module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb28().set(Mstpb28::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the SCI4 module
+pub fn enable_sci4(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable SCI4 module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb27().set(Mstpb27::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the SCI9 module
+pub fn enable_sci9(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable SCI9 module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb22().set(Mstpb22::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the SPI0 module
+pub fn enable_spi0(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable SPI0 module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb19().set(Mstpb19::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the QSPI module
+pub fn enable_qspi(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable QSPI module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrb().modify(|w| w.mstpb6().set(Mstpb6::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrb().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for GPT channel `channel` (0-3:
+/// GPT320-GPT323, 4-9: GPT164-GPT169), one MSTPCRE bit per channel counting
+/// down from MSTPE31
+pub fn enable_gpt(cs: &cortex_m::interrupt::CriticalSection, channel: u8) {
+    // Enable GPT channel
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcre().modify(|w| w.set_raw(w.get_raw() & !(1 << (31 - channel)))); // Set the bit to 0 to enable
+            let _ = mstp.mstpcre().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the POEG module (all groups)
+pub fn enable_poeg(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable POEG module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrd().modify(|w| w.mstpd14().set(Mstpd14::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrd().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
+/// Enables the power management system for the ELC module
+pub fn enable_elc(cs: &cortex_m::interrupt::CriticalSection) {
+    // Enable ELC module
+    unsafe {
+        if let Some(mstp) = POWER.borrow(cs).borrow_mut().as_mut() {
+            mstp.mstpcrc().modify(|w| w.mstpc14().set(Mstpc14::_0)); // Set the bit to 0 to enable
+            let _ = mstp.mstpcrc().read();
+            cortex_m::asm::dsb();
+        }
+    }
+}
+
 /// Enables the power management system for the AGT0 module
 pub fn enable_agt0(cs: &cortex_m::interrupt::CriticalSection) {
     // Enable AGT0 module
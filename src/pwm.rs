@@ -0,0 +1,283 @@
+//! PWM on a GPT channel, on its GTIOCnA and/or GTIOCnB outputs.
+//!
+//! In saw-wave mode the counter runs up from 0 to GTPR and the outputs go
+//! active at the start of each cycle. In triangle-wave mode it runs up to GTPR
+//! and back down, giving pulses centred on the trough at half the frequency
+//! of a saw wave with the same GTPR.
+//!
+//! Duty cycles and the period are written to the buffer registers (GTCCRC /
+//! GTCCRE, GTPBR), which the hardware copies over at the end of a cycle (at
+//! the trough in triangle mode), so updates never cut a pulse short. 0 % and
+//! 100 % are produced with the forced duty settings (GTUDDTYC.OADTY /
+//! OBDTY), which also take effect at the end of a cycle.
+
+use core::{convert::Infallible, marker::PhantomData};
+
+use ra4m2_pac::{gpt320::{gtcr::Cst, gtior::{Oadflt, Oae, Obdflt, Obe}, gtuddtyc::{Ud, Udf}}, NoBitfieldReg, RegisterValue};
+
+use crate::{gpt::{self, GtiocaPin, GtiocbPin, Instance, GTCCRA, GTCCRB, GTCCRC, GTCCRE}, sysc::SystemClock};
+
+/// Counter waveform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PwmMode {
+    /// Counts up, edge-aligned pulses.
+    Saw,
+    /// Counts up and down, centre-aligned pulses.
+    Triangle,
+}
+
+/// Level of an output during the duty part of the cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// PWM configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PwmConfig {
+    /// PWM frequency in Hz, rounded to the nearest rate the counter allows.
+    pub frequency: u32,
+    pub mode: PwmMode,
+}
+
+impl Default for PwmConfig {
+    fn default() -> Self {
+        PwmConfig {
+            frequency: 20_000,
+            mode: PwmMode::Saw,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    A,
+    B,
+}
+
+// GTIOR.GTIOA/GTIOB: initial level (4), compare match action (3:2) and cycle
+// end action (1:0), each 01 = low, 10 = high, 11 = toggle, 00 = retain.
+const SAW_ACTIVE_HIGH: u8 = 0b1_01_10;
+const SAW_ACTIVE_LOW: u8 = 0b0_10_01;
+const TRIANGLE_ACTIVE_HIGH: u8 = 0b1_11_00;
+const TRIANGLE_ACTIVE_LOW: u8 = 0b0_11_00;
+
+// GTUDDTYC.OADTY/OBDTY forced duty settings.
+const DUTY_NORMAL: u8 = 0b00;
+const DUTY_0: u8 = 0b10;
+const DUTY_100: u8 = 0b11;
+
+/// Counts per PWM period for a GTPR value: one more than GTPR for a saw
+/// wave, GTPR up and GTPR down for a triangle wave (of which the duty
+/// setting spans half).
+fn period_counts(mode: PwmMode, gtpr: u32) -> u64 {
+    match mode {
+        PwmMode::Saw => gtpr as u64 + 1,
+        PwmMode::Triangle => gtpr as u64,
+    }
+}
+
+/// GTPR value for `frequency` at a count clock of `count_hz`, or `None` for
+/// a frequency of 0.
+fn gtpr_setting(mode: PwmMode, count_hz: u32, frequency: u32) -> Option<u64> {
+    if frequency == 0 {
+        return None;
+    }
+    let counts_per_cycle = match mode {
+        PwmMode::Saw => 1,
+        PwmMode::Triangle => 2,
+    } * frequency as u64;
+    let counts = (count_hz as u64 + counts_per_cycle / 2) / counts_per_cycle;
+    Some(match mode {
+        PwmMode::Saw => counts.saturating_sub(1),
+        PwmMode::Triangle => counts,
+    })
+}
+
+/// PWM driver on GPT channel `T`. Output pins are borrowed for `'d` the same
+/// way as for `i2c::I2c`.
+pub struct Pwm<'d, T: Instance> {
+    gpt: T,
+    mode: PwmMode,
+    /// Count clock frequency in Hz.
+    count_hz: u32,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> Pwm<'d, T> {
+    /// Sets up the counter with both outputs disabled; the timer doesn't run
+    /// until `start`. Panics if `config.frequency` can't be reached from
+    /// PCLKD.
+    pub fn new(gpt: T, system_clock: &SystemClock, config: PwmConfig) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        let pclkd = system_clock.get_pclkd_freq();
+        let (tpcs, gtpr) = gpt::prescaler_setting(pclkd, T::MAX_COUNT, |count_hz| {
+            // GTPR must be at least 1; 0 is rejected for a frequency of 0
+            gtpr_setting(config.mode, count_hz, config.frequency).map_or(0, |gtpr| gtpr.max(1))
+        })
+        .expect("PWM frequency out of range for PCLKD");
+        let md = match config.mode {
+            PwmMode::Saw => 0b000,
+            // Triangle-wave PWM mode 1: buffers transferred at the trough
+            PwmMode::Triangle => 0b100,
+        };
+
+        unsafe {
+            let regs = T::regs();
+            regs.gtcr().modify(|w| w.set_raw(0));
+            regs.gtcr().modify(|w| w.md().set(md.into()).tpcs().set(tpcs.into()));
+            regs.gtuddtyc().modify(|w| w.set_raw(0).ud().set(Ud::_1).udf().set(Udf::_0));
+            regs.gtior().modify(|w| w.set_raw(0));
+            // Single buffer operation for GTCCRA, GTCCRB and GTPR
+            regs.gtber().modify(|w| w.set_raw(0).ccra().set(1.into()).ccrb().set(1.into()).pr().set(1.into()));
+            regs.gtpr().modify(|w| w.set(gtpr));
+            regs.gtpbr().modify(|w| w.set(gtpr));
+            for index in [GTCCRA, GTCCRB, GTCCRC, GTCCRE] {
+                regs.gtccr().get(index).modify(|w| w.set(0));
+            }
+            regs.gtcnt().modify(|w| w.set(0));
+        }
+
+        Pwm {
+            gpt,
+            mode: config.mode,
+            count_hz: pclkd / gpt::prescaler_divider(tpcs),
+            _pins: PhantomData,
+        }
+    }
+
+    /// Stops the counter and releases the channel.
+    pub fn free(self) -> T {
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_0));
+            T::regs().gtior().modify(|w| w.set_raw(0));
+        }
+        self.gpt
+    }
+
+    fn gtio_setting(&self, polarity: Polarity) -> u8 {
+        match (self.mode, polarity) {
+            (PwmMode::Saw, Polarity::ActiveHigh) => SAW_ACTIVE_HIGH,
+            (PwmMode::Saw, Polarity::ActiveLow) => SAW_ACTIVE_LOW,
+            (PwmMode::Triangle, Polarity::ActiveHigh) => TRIANGLE_ACTIVE_HIGH,
+            (PwmMode::Triangle, Polarity::ActiveLow) => TRIANGLE_ACTIVE_LOW,
+        }
+    }
+
+    /// Drives GTIOCnA, at 0 % duty until set. While the counter is stopped
+    /// the pin sits at its inactive level.
+    pub fn enable_a<P: GtiocaPin<T>>(&mut self, _pin: &'d mut P, polarity: Polarity) {
+        let gtioa = self.gtio_setting(polarity);
+        let oadflt = match polarity {
+            Polarity::ActiveHigh => Oadflt::_0,
+            Polarity::ActiveLow => Oadflt::_1,
+        };
+        unsafe {
+            T::regs().gtuddtyc().modify(|w| w.oadty().set(DUTY_0.into()));
+            T::regs().gtior().modify(|w| w.gtioa().set(gtioa.into()).oadflt().set(oadflt).oae().set(Oae::_1));
+        }
+    }
+
+    /// Drives GTIOCnB, at 0 % duty until set. While the counter is stopped
+    /// the pin sits at its inactive level.
+    pub fn enable_b<P: GtiocbPin<T>>(&mut self, _pin: &'d mut P, polarity: Polarity) {
+        let gtiob = self.gtio_setting(polarity);
+        let obdflt = match polarity {
+            Polarity::ActiveHigh => Obdflt::_0,
+            Polarity::ActiveLow => Obdflt::_1,
+        };
+        unsafe {
+            T::regs().gtuddtyc().modify(|w| w.obdty().set(DUTY_0.into()));
+            T::regs().gtior().modify(|w| w.gtiob().set(gtiob.into()).obdflt().set(obdflt).obe().set(Obe::_1));
+        }
+    }
+
+    pub fn start(&mut self) {
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_1));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        unsafe {
+            T::regs().gtcr().modify(|w| w.cst().set(Cst::_0));
+        }
+    }
+
+    /// Changes the frequency from the next cycle on, keeping the prescaler.
+    /// Duty cycles are held in counts, so set them again afterwards. Panics
+    /// if `frequency` can't be reached at the current prescaler.
+    pub fn set_frequency(&mut self, frequency: u32) {
+        let gtpr = gtpr_setting(self.mode, self.count_hz, frequency)
+            .filter(|&gtpr| gtpr >= 1 && gtpr <= T::MAX_COUNT as u64)
+            .expect("PWM frequency out of range for the prescaler");
+        unsafe {
+            T::regs().gtpbr().modify(|w| w.set(gtpr as u32));
+        }
+    }
+
+    /// Duty cycle handles for GTIOCnA and GTIOCnB.
+    pub fn split(&mut self) -> (PwmOutput<'_, T>, PwmOutput<'_, T>) {
+        (
+            PwmOutput { output: Output::A, mode: self.mode, _pwm: PhantomData },
+            PwmOutput { output: Output::B, mode: self.mode, _pwm: PhantomData },
+        )
+    }
+}
+
+/// One output of a `Pwm`. Setting the duty cycle of an output that hasn't
+/// been enabled has no visible effect.
+pub struct PwmOutput<'a, T: Instance> {
+    output: Output,
+    mode: PwmMode,
+    _pwm: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Instance> PwmOutput<'a, T> {
+    /// Counts in one period at the newest period setting.
+    fn counts(&self) -> u64 {
+        period_counts(self.mode, unsafe { T::regs().gtpbr().read().get() })
+    }
+}
+
+impl<'a, T: Instance> embedded_hal::pwm::ErrorType for PwmOutput<'a, T> {
+    type Error = Infallible;
+}
+
+impl<'a, T: Instance> embedded_hal::pwm::SetDutyCycle for PwmOutput<'a, T> {
+    /// The period in counts, scaled down to fit a `u16` for long periods.
+    fn max_duty_cycle(&self) -> u16 {
+        self.counts().min(u16::MAX as u64) as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        let max = self.max_duty_cycle();
+        let counts = duty as u64 * self.counts() / max as u64;
+        let forced = if duty == 0 {
+            DUTY_0
+        } else if duty >= max {
+            DUTY_100
+        } else {
+            DUTY_NORMAL
+        };
+
+        unsafe {
+            let regs = T::regs();
+            match self.output {
+                Output::A => {
+                    regs.gtccr().get(GTCCRC).modify(|w| w.set(counts as u32));
+                    regs.gtuddtyc().modify(|w| w.oadty().set(forced.into()));
+                }
+                Output::B => {
+                    regs.gtccr().get(GTCCRE).modify(|w| w.set(counts as u32));
+                    regs.gtuddtyc().modify(|w| w.obdty().set(forced.into()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
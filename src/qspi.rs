@@ -0,0 +1,293 @@
+//! Quad SPI (QSPI) serial flash interface, memory-mapped (XIP) reads.
+//!
+//! In ROM access mode the peripheral turns CPU and DMA reads of the ROM area
+//! at 0x6000_0000 into read commands to the flash, so data and code can be
+//! used in place. The read command is chosen by `ReadMode` and clocked at
+//! PCLKA / n (n = 2 to 48).
+//!
+//! `build.rs` adds a `QSPI_FLASH` memory region and a `.qspi_flash` output
+//! section when the `qspi` feature is enabled, so items can be placed there
+//! with `#[link_section = ".qspi_flash"]`. The region is 16 MiB unless the
+//! `RA4M2_QSPI_FLASH_SIZE` environment variable gives another linker length
+//! (e.g. `8M`). Nothing in it may be touched before `Qspi::new` has run, and
+//! the debug probe needs a flash algorithm for the external part to program
+//! it.
+//!
+//! `Qspi::command` issues other flash commands in direct communication mode,
+//! which `qspi_flash` builds on. The ROM area can't be read while a command
+//! runs, so commands run with interrupts masked and must not be called from
+//! code that lives in the external flash.
+//!
+//! `Qspi` is behind the `qspi` Cargo feature.
+
+use core::marker::PhantomData;
+
+use ra4m2_pac::{qspi::{sfmcmd::Dcom, sfmsmd::Sfmpfe}, RegisterValue};
+
+use crate::{gpio::{AlternateFunction, Output}, power, sysc::SystemClock};
+
+/// Start of the ROM access area.
+pub const ROM_ADDRESS: usize = 0x6000_0000;
+/// Size of the ROM access area. Flash beyond it is not mapped.
+pub const ROM_SIZE: usize = 0x0400_0000;
+
+/// Read command issued for ROM area accesses. The quad modes need the flash's
+/// quad enable bit set and the `new_quad` pins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Read (03h). Most parts limit its clock to well below the fast reads'.
+    Standard = 0,
+    /// Fast Read (0Bh).
+    Fast = 1,
+    /// Fast Read Dual Output (3Bh).
+    FastDualOutput = 2,
+    /// Fast Read Dual I/O (BBh).
+    FastDualIo = 3,
+    /// Fast Read Quad Output (6Bh).
+    FastQuadOutput = 4,
+    /// Fast Read Quad I/O (EBh).
+    FastQuadIo = 5,
+}
+
+impl ReadMode {
+    fn is_quad(self) -> bool {
+        matches!(self, ReadMode::FastQuadOutput | ReadMode::FastQuadIo)
+    }
+}
+
+/// Number of address bytes sent with each command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSize {
+    ThreeBytes,
+    /// For parts over 16 MiB. The flash must be in its 4-byte address mode.
+    FourBytes,
+}
+
+/// QSPI configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QspiConfig {
+    /// QSPCLK frequency in Hz. The fastest rate not above it is used.
+    pub frequency: u32,
+    pub read_mode: ReadMode,
+    pub address_size: AddressSize,
+    /// Dummy cycles between address and data, 3 to 17. `None` uses the read
+    /// mode's default (8 for the fast reads, 4 for Quad I/O).
+    pub dummy_cycles: Option<u8>,
+    /// Flash size in bytes, up to `ROM_SIZE`; the length of `Qspi::rom`.
+    pub flash_size: usize,
+    /// Prefetches sequential ROM reads.
+    pub prefetch: bool,
+}
+
+impl Default for QspiConfig {
+    fn default() -> Self {
+        QspiConfig {
+            frequency: 30_000_000,
+            read_mode: ReadMode::Fast,
+            address_size: AddressSize::ThreeBytes,
+            dummy_cycles: None,
+            flash_size: 16 * 1024 * 1024,
+            prefetch: true,
+        }
+    }
+}
+
+/// Picks the clock divider setting (SFMSKC.SFMDV) for the fastest QSPCLK not
+/// above `frequency`. Settings 0-16 divide by 2-18, 17-31 by 20-48 in steps
+/// of two.
+fn clock_divider_setting(pclk: u32, frequency: u32) -> Option<u8> {
+    (0..32u8).find(|&setting| {
+        let divider = if setting <= 16 { setting as u32 + 2 } else { 2 * (setting as u32 - 16) + 18 };
+        pclk / divider <= frequency
+    })
+}
+
+/// Marks a pin that can carry QSPCLK.
+pub trait QspclkPin {}
+/// Marks a pin that can carry QSSL.
+pub trait QsslPin {}
+/// Marks a pin that can carry QIO0.
+pub trait Qio0Pin {}
+/// Marks a pin that can carry QIO1.
+pub trait Qio1Pin {}
+/// Marks a pin that can carry QIO2.
+pub trait Qio2Pin {}
+/// Marks a pin that can carry QIO3.
+pub trait Qio3Pin {}
+
+macro_rules! impl_qspi_pin {
+    ($feature:literal, $port:ident, $n:literal, $pin_trait:ident) => {
+        #[cfg(feature = $feature)]
+        impl $pin_trait for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {}
+    };
+}
+
+// Pin assignments from the "Peripheral Select Settings" tables of the
+// RA4M2 User's Manual. Mux with `PinFunction::QSPI`.
+impl_qspi_pin!("port3", port3, 5, QspclkPin);
+impl_qspi_pin!("port3", port3, 6, QsslPin);
+impl_qspi_pin!("port3", port3, 7, Qio0Pin);
+impl_qspi_pin!("port3", port3, 8, Qio1Pin);
+impl_qspi_pin!("port3", port3, 9, Qio2Pin);
+impl_qspi_pin!("port3", port3, 10, Qio3Pin);
+impl_qspi_pin!("port5", port5, 0, QspclkPin);
+impl_qspi_pin!("port5", port5, 1, QsslPin);
+impl_qspi_pin!("port5", port5, 2, Qio0Pin);
+impl_qspi_pin!("port5", port5, 3, Qio1Pin);
+impl_qspi_pin!("port5", port5, 4, Qio2Pin);
+impl_qspi_pin!("port5", port5, 5, Qio3Pin);
+
+/// QSPI driver. Pins are borrowed for `'d` the same way as for `i2c::I2c`.
+pub struct Qspi<'d> {
+    qspi: ra4m2_pac::Qspi,
+    flash_size: usize,
+    address_size: AddressSize,
+    quad: bool,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d> Qspi<'d> {
+    /// Sets up single or dual I/O operation and enters ROM access mode. The
+    /// flash's WP# and HOLD# pins are left to the caller (e.g. held high by
+    /// GPIOs). Panics on a quad `read_mode`, or if `config.frequency` can't
+    /// be reached from PCLKA.
+    pub fn new<CLK: QspclkPin, SSL: QsslPin, IO0: Qio0Pin, IO1: Qio1Pin>(
+        qspi: ra4m2_pac::Qspi,
+        _clk: &'d mut CLK,
+        _ssl: &'d mut SSL,
+        _io0: &'d mut IO0,
+        _io1: &'d mut IO1,
+        system_clock: &SystemClock,
+        config: QspiConfig,
+    ) -> Self {
+        assert!(!config.read_mode.is_quad(), "quad read modes need new_quad");
+        Self::init(qspi, false, system_clock, config)
+    }
+
+    /// Sets up quad I/O operation, with QIO2 and QIO3 in place of WP# and
+    /// HOLD#, and enters ROM access mode.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_quad<CLK: QspclkPin, SSL: QsslPin, IO0: Qio0Pin, IO1: Qio1Pin, IO2: Qio2Pin, IO3: Qio3Pin>(
+        qspi: ra4m2_pac::Qspi,
+        _clk: &'d mut CLK,
+        _ssl: &'d mut SSL,
+        _io0: &'d mut IO0,
+        _io1: &'d mut IO1,
+        _io2: &'d mut IO2,
+        _io3: &'d mut IO3,
+        system_clock: &SystemClock,
+        config: QspiConfig,
+    ) -> Self {
+        Self::init(qspi, true, system_clock, config)
+    }
+
+    fn init(qspi: ra4m2_pac::Qspi, quad: bool, system_clock: &SystemClock, config: QspiConfig) -> Self {
+        assert!(config.flash_size <= ROM_SIZE, "flash larger than the ROM area");
+        let sfmdv = clock_divider_setting(system_clock.get_pclka_freq(), config.frequency)
+            .expect("QSPI frequency out of range for PCLKA");
+        // SFMDN = cycles - 2, with 0 selecting the default
+        let sfmdn = match config.dummy_cycles {
+            Some(cycles) => {
+                assert!((3..=17).contains(&cycles), "dummy cycles out of range");
+                cycles - 2
+            }
+            None => 0,
+        };
+        let sfmas = match config.address_size {
+            AddressSize::ThreeBytes => 2,
+            AddressSize::FourBytes => 3,
+        };
+
+        cortex_m::interrupt::free(|cs| {
+            power::enable_qspi(cs);
+        });
+
+        unsafe {
+            qspi.sfmcmd().modify(|w| w.dcom().set(Dcom::_0));
+            qspi.sfmskc().modify(|w| w.set_raw(0).sfmdv().set(sfmdv.into()));
+            qspi.sfmsac().modify(|w| w.set_raw(0).sfmas().set(sfmas.into()));
+            qspi.sfmsdc().modify(|w| w.sfmdn().set(sfmdn.into()));
+            // Extended SPI protocol: commands on QIO0, then the read mode's
+            // address and data widths
+            qspi.sfmspc().modify(|w| w.sfmspi().set(0.into()));
+        }
+
+        let mut qspi = Qspi {
+            qspi,
+            flash_size: config.flash_size,
+            address_size: config.address_size,
+            quad,
+            _pins: PhantomData,
+        };
+        qspi.set_read_mode(config.read_mode);
+        qspi.set_prefetch(config.prefetch);
+        qspi
+    }
+
+    /// Releases the peripheral. The ROM area is unusable afterwards.
+    pub fn free(self) -> ra4m2_pac::Qspi {
+        self.qspi
+    }
+
+    /// Changes the read command, e.g. to a quad read once the flash's quad
+    /// enable bit is set. Panics on a quad mode without the quad pins.
+    pub fn set_read_mode(&mut self, mode: ReadMode) {
+        assert!(self.quad || !mode.is_quad(), "quad read modes need new_quad");
+        unsafe {
+            self.qspi.sfmsmd().modify(|w| w.sfmrm().set((mode as u8).into()));
+        }
+        // Later ROM reads must use the new command
+        cortex_m::asm::dsb();
+    }
+
+    pub fn set_prefetch(&mut self, enabled: bool) {
+        let sfmpfe = if enabled { Sfmpfe::_1 } else { Sfmpfe::_0 };
+        unsafe {
+            self.qspi.sfmsmd().modify(|w| w.sfmpfe().set(sfmpfe));
+        }
+    }
+
+    /// Runs one command in direct communication mode, on QIO0/QIO1 only:
+    /// sends the `command` byte, `address` (3 or 4 bytes, as configured) and
+    /// `write`, then clocks in `read`. Returns to ROM access mode afterwards.
+    pub fn command(&mut self, command: u8, address: Option<u32>, write: &[u8], read: &mut [u8]) {
+        let address_bytes = match self.address_size {
+            AddressSize::ThreeBytes => 3,
+            AddressSize::FourBytes => 4,
+        };
+
+        cortex_m::interrupt::free(|_| unsafe {
+            let qspi = &self.qspi;
+            qspi.sfmcmd().modify(|w| w.dcom().set(Dcom::_1));
+
+            // Plain writes; reading SFMCOM clocks in a byte
+            qspi.sfmcom().init(|w| w.sfmd().set(command.into()));
+            if let Some(address) = address {
+                for shift in (0..address_bytes).rev() {
+                    qspi.sfmcom().init(|w| w.sfmd().set(((address >> (8 * shift)) as u8).into()));
+                }
+            }
+            for &byte in write {
+                qspi.sfmcom().init(|w| w.sfmd().set(byte.into()));
+            }
+            for byte in read.iter_mut() {
+                *byte = qspi.sfmcom().read().sfmd().get();
+            }
+
+            // Writing SFMCMD negates QSSL, ending the command
+            qspi.sfmcmd().modify(|w| w.dcom().set(Dcom::_1));
+            qspi.sfmcmd().modify(|w| w.dcom().set(Dcom::_0));
+        });
+        cortex_m::asm::dsb();
+    }
+
+    /// Flash size in bytes, from `QspiConfig::flash_size`.
+    pub fn flash_size(&self) -> usize {
+        self.flash_size
+    }
+
+    /// The flash contents, as mapped into the ROM area.
+    pub fn rom(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(ROM_ADDRESS as *const u8, self.flash_size) }
+    }
+}
@@ -0,0 +1,232 @@
+//! Serial NOR flash on the QSPI, through `embedded_storage::nor_flash`.
+//!
+//! Uses the common SPI NOR command set over `Qspi::command`: Read Data
+//! (03h), Page Program (02h), 4 KiB Sector Erase (20h), Write Enable (06h)
+//! and Read Status Register (05h), polling WIP after each program or erase.
+//! The flash size is `QspiConfig::flash_size`.
+//!
+//! Programming and erasing leave the ROM area readable again afterwards, but
+//! neither this driver nor anything it calls may run from the external flash.
+
+use embassy_time::Duration;
+use embedded_storage::nor_flash::{ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+use crate::{qspi::{Qspi, ReadMode}, timeout::Deadline};
+
+const READ_DATA: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const SECTOR_ERASE: u8 = 0x20;
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS_1: u8 = 0x05;
+const READ_STATUS_2: u8 = 0x35;
+const WRITE_STATUS_1: u8 = 0x01;
+const WRITE_STATUS_2: u8 = 0x31;
+const READ_JEDEC_ID: u8 = 0x9F;
+
+/// Status register 1: write in progress.
+const STATUS_WIP: u8 = 1 << 0;
+
+/// Bytes one Page Program can write.
+pub const PAGE_SIZE: usize = 256;
+/// Bytes one Sector Erase clears.
+pub const SECTOR_SIZE: usize = 4096;
+
+/// Bytes moved per direct read command, bounding how long interrupts stay
+/// masked.
+const READ_CHUNK: usize = 256;
+
+/// Where the flash keeps its quad enable (QE) bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuadEnable {
+    /// Status register 2 bit 1, written with 31h (Winbond, GigaDevice).
+    Status2Bit1,
+    /// Status register 1 bit 6, written with 01h (Macronix, ISSI).
+    Status1Bit6,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QspiFlashConfig {
+    pub quad_enable: QuadEnable,
+    /// Longest a Page Program may take.
+    pub program_timeout: Duration,
+    /// Longest a Sector Erase may take.
+    pub erase_timeout: Duration,
+}
+
+impl Default for QspiFlashConfig {
+    fn default() -> Self {
+        QspiFlashConfig {
+            quad_enable: QuadEnable::Status2Bit1,
+            program_timeout: Duration::from_millis(5),
+            erase_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum QspiFlashError {
+    /// The range runs past the end of the flash.
+    OutOfBounds,
+    /// An erase range doesn't start and end on sector boundaries.
+    NotAligned,
+    /// The flash stayed busy past the configured timeout.
+    Timeout,
+}
+
+impl NorFlashError for QspiFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match *self {
+            QspiFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            QspiFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            QspiFlashError::Timeout => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// NOR flash driver on top of `Qspi`.
+pub struct QspiFlash<'d> {
+    qspi: Qspi<'d>,
+    config: QspiFlashConfig,
+}
+
+impl<'d> QspiFlash<'d> {
+    pub fn new(qspi: Qspi<'d>, config: QspiFlashConfig) -> Self {
+        QspiFlash { qspi, config }
+    }
+
+    /// Hands back the QSPI driver.
+    pub fn free(self) -> Qspi<'d> {
+        self.qspi
+    }
+
+    /// Manufacturer ID followed by the two device ID bytes.
+    pub fn jedec_id(&mut self) -> [u8; 3] {
+        let mut id = [0; 3];
+        self.qspi.command(READ_JEDEC_ID, None, &[], &mut id);
+        id
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = [0];
+        self.qspi.command(READ_STATUS_1, None, &[], &mut status);
+        status[0]
+    }
+
+    pub fn write_enable(&mut self) {
+        self.qspi.command(WRITE_ENABLE, None, &[], &mut []);
+    }
+
+    /// Polls WIP until the flash is idle.
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), QspiFlashError> {
+        let deadline = Deadline::after(timeout);
+        while self.read_status() & STATUS_WIP != 0 {
+            if deadline.expired() {
+                return Err(QspiFlashError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the flash's non-volatile QE bit, then switches ROM reads to
+    /// `mode`. Panics unless the `Qspi` was created with `new_quad`.
+    pub fn enable_quad(&mut self, mode: ReadMode) -> Result<(), QspiFlashError> {
+        match self.config.quad_enable {
+            QuadEnable::Status2Bit1 => {
+                let mut status = [0];
+                self.qspi.command(READ_STATUS_2, None, &[], &mut status);
+                self.write_enable();
+                self.qspi.command(WRITE_STATUS_2, None, &[status[0] | 1 << 1], &mut []);
+            }
+            QuadEnable::Status1Bit6 => {
+                let status = self.read_status();
+                self.write_enable();
+                self.qspi.command(WRITE_STATUS_1, None, &[status | 1 << 6], &mut []);
+            }
+        }
+        // Status register writes take as long as a page program at most
+        self.wait_ready(self.config.program_timeout)?;
+        self.qspi.set_read_mode(mode);
+        Ok(())
+    }
+
+    /// Erases the 4 KiB sector containing `address`.
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), QspiFlashError> {
+        self.write_enable();
+        self.qspi.command(SECTOR_ERASE, Some(address), &[], &mut []);
+        self.wait_ready(self.config.erase_timeout)
+    }
+
+    /// Programs `bytes` at `address`, which must not cross a page boundary.
+    pub fn program_page(&mut self, address: u32, bytes: &[u8]) -> Result<(), QspiFlashError> {
+        debug_assert!(address as usize % PAGE_SIZE + bytes.len() <= PAGE_SIZE);
+        self.write_enable();
+        self.qspi.command(PAGE_PROGRAM, Some(address), bytes, &mut []);
+        self.wait_ready(self.config.program_timeout)
+    }
+
+    fn check_bounds(&self, offset: u32, len: usize) -> Result<(), QspiFlashError> {
+        match (offset as usize).checked_add(len) {
+            Some(end) if end <= self.qspi.flash_size() => Ok(()),
+            _ => Err(QspiFlashError::OutOfBounds),
+        }
+    }
+}
+
+impl<'d> ErrorType for QspiFlash<'d> {
+    type Error = QspiFlashError;
+}
+
+impl<'d> ReadNorFlash for QspiFlash<'d> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_bounds(offset, bytes.len())?;
+        let mut address = offset;
+        for chunk in bytes.chunks_mut(READ_CHUNK) {
+            self.qspi.command(READ_DATA, Some(address), &[], chunk);
+            address += chunk.len() as u32;
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.qspi.flash_size()
+    }
+}
+
+impl<'d> NorFlash for QspiFlash<'d> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to {
+            return Err(QspiFlashError::OutOfBounds);
+        }
+        self.check_bounds(from, (to - from) as usize)?;
+        if from as usize % SECTOR_SIZE != 0 || to as usize % SECTOR_SIZE != 0 {
+            return Err(QspiFlashError::NotAligned);
+        }
+        for address in (from..to).step_by(SECTOR_SIZE) {
+            self.erase_sector(address)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check_bounds(offset, bytes.len())?;
+        let mut address = offset;
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let len = remaining.len().min(PAGE_SIZE - address as usize % PAGE_SIZE);
+            let (page, rest) = remaining.split_at(len);
+            self.program_page(address, page)?;
+            address += len as u32;
+            remaining = rest;
+        }
+        Ok(())
+    }
+}
+
+// Programming only clears bits, so a word can be written again without an
+// erase.
+impl<'d> MultiwriteNorFlash for QspiFlash<'d> {}
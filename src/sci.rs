@@ -0,0 +1,216 @@
+//! Serial Communications Interface (SCI) channels shared by the SCI drivers
+//! (simple I2C, UART, simple SPI, ...).
+//!
+//! The RA4M2 has SCI0-SCI4 and SCI9, each behind a Cargo feature of the same
+//! name. All channels share one PAC register-block type, so the channel
+//! markers below check the token they are created from against the
+//! channel's address at run time.
+
+use core::{cell::Cell, sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize}};
+
+use cortex_m::interrupt::Mutex;
+use ra4m2_pac::{Gpt320 as GptRegs, Sci0 as SciRegs};
+
+use crate::{gpio::{AlternateFunction, Output, PinFunction}, power, sealed, waker::WakerSlot};
+
+/// Offset of the transmit data register (TDR) from the channel base.
+pub(crate) const TDR_OFFSET: u32 = 0x03;
+/// Offset of the receive data register (RDR) from the channel base.
+pub(crate) const RDR_OFFSET: u32 = 0x05;
+
+/// Interrupt-shared state of one channel, used by the async drivers.
+#[doc(hidden)]
+pub struct State {
+    pub(crate) rx_waker: WakerSlot,
+    pub(crate) tx_waker: WakerSlot,
+    /// SSR error bits (ORER, FER, PER) latched by the ERI handler.
+    pub(crate) rx_errors: AtomicU8,
+    pub(crate) rx_transfer: crate::dtc::TransferInfo,
+    /// Length of the DTC receive ring.
+    pub(crate) rx_len: AtomicUsize,
+    /// Ring index the DTC wrote next when `rx_written` was last updated.
+    pub(crate) rx_last_pos: AtomicUsize,
+    /// Bytes the DTC has written into the ring since it was set up, wrapping.
+    pub(crate) rx_written: AtomicU32,
+    /// GPT channel timing the line idle for `read_until_idle`, with its
+    /// GTSTR/GTCLR bit.
+    pub(crate) idle_timer: Mutex<Cell<Option<(fn() -> GptRegs, u32)>>>,
+    /// The idle timer ran out since the last received byte.
+    pub(crate) rx_idle: AtomicBool,
+}
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        State {
+            rx_waker: WakerSlot::new(),
+            tx_waker: WakerSlot::new(),
+            rx_errors: AtomicU8::new(0),
+            rx_transfer: crate::dtc::TransferInfo::new(),
+            rx_len: AtomicUsize::new(1),
+            rx_last_pos: AtomicUsize::new(0),
+            rx_written: AtomicU32::new(0),
+            idle_timer: Mutex::new(Cell::new(None)),
+            rx_idle: AtomicBool::new(false),
+        }
+    }
+}
+
+/// An SCI channel. Sealed so drivers can rely on the register layout, event
+/// numbers and pin function.
+pub trait Instance: sealed::Sealed {
+    /// ICU event number for receive data full (RXI).
+    const RXI_EVENT: u16;
+    /// ICU event number for transmit data empty (TXI).
+    const TXI_EVENT: u16;
+    /// ICU event number for transmit end (TEI).
+    const TEI_EVENT: u16;
+    /// ICU event number for receive error (ERI).
+    const ERI_EVENT: u16;
+    /// Peripheral function the channel's pins are muxed to: `SCIA` for even
+    /// channels, `SCIB` for odd ones.
+    const PIN_FUNCTION: PinFunction;
+    /// Base address of the channel's registers, for DTC transfers.
+    const ADDRESS: u32;
+
+    /// Register block of this channel.
+    fn regs() -> SciRegs;
+
+    /// Releases the channel from module stop.
+    fn enable_power(cs: &cortex_m::interrupt::CriticalSection);
+
+    /// Interrupt-shared state of this channel.
+    #[doc(hidden)]
+    fn state() -> &'static State;
+}
+
+macro_rules! sci_instance {
+    ($feature:literal, $name:ident, $regs:ident, $power_func:ident, $function:ident, $address:literal, $rxi:literal, $txi:literal, $tei:literal, $eri:literal) => {
+        /// Owned SCI channel, created from its PAC token and handing it back
+        /// from `free`.
+        #[cfg(feature = $feature)]
+        pub struct $name {
+            sci: SciRegs,
+        }
+
+        #[cfg(feature = $feature)]
+        impl $name {
+            /// Panics on another channel's token.
+            pub fn new(sci: SciRegs) -> Self {
+                assert!(sci == ra4m2_pac::$regs, "PAC token of another SCI channel");
+                $name { sci }
+            }
+
+            /// Returns the PAC token.
+            pub fn free(self) -> SciRegs {
+                self.sci
+            }
+        }
+
+        #[cfg(feature = $feature)]
+        impl sealed::Sealed for $name {}
+
+        #[cfg(feature = $feature)]
+        impl Instance for $name {
+            const RXI_EVENT: u16 = $rxi;
+            const TXI_EVENT: u16 = $txi;
+            const TEI_EVENT: u16 = $tei;
+            const ERI_EVENT: u16 = $eri;
+            const PIN_FUNCTION: PinFunction = PinFunction::$function;
+            const ADDRESS: u32 = $address;
+
+            fn regs() -> SciRegs {
+                ra4m2_pac::$regs
+            }
+
+            fn enable_power(cs: &cortex_m::interrupt::CriticalSection) {
+                power::$power_func(cs);
+            }
+
+            fn state() -> &'static State {
+                static STATE: State = State::new();
+                &STATE
+            }
+        }
+    };
+}
+
+sci_instance!("sci0", Sci0, SCI0, enable_sci0, SCIA, 0x4011_8000, 0x098, 0x099, 0x09A, 0x09B);
+sci_instance!("sci1", Sci1, SCI1, enable_sci1, SCIB, 0x4011_8100, 0x09E, 0x09F, 0x0A0, 0x0A1);
+sci_instance!("sci2", Sci2, SCI2, enable_sci2, SCIA, 0x4011_8200, 0x0A3, 0x0A4, 0x0A5, 0x0A6);
+sci_instance!("sci3", Sci3, SCI3, enable_sci3, SCIB, 0x4011_8300, 0x0A8, 0x0A9, 0x0AA, 0x0AB);
+sci_instance!("sci4", Sci4, SCI4, enable_sci4, SCIA, 0x4011_8400, 0x0AD, 0x0AE, 0x0AF, 0x0B0);
+sci_instance!("sci9", Sci9, SCI9, enable_sci9, SCIB, 0x4011_8900, 0x0C6, 0x0C7, 0x0C8, 0x0C9);
+
+/// Marks a pin that can carry TXDn / SDAn / MOSIn of SCI channel `T`. Pins
+/// must be muxed to `T::PIN_FUNCTION` with `into_alternate_function` first.
+pub trait TxdPin<T: Instance> {}
+/// Marks a pin that can carry RXDn / SCLn / MISOn of SCI channel `T`.
+pub trait RxdPin<T: Instance> {}
+/// Marks a pin that can carry SCKn of SCI channel `T`.
+pub trait SckPin<T: Instance> {}
+/// Marks a pin that can carry CTSn_RTSn / SSn of SCI channel `T`.
+pub trait CtsRtsPin<T: Instance> {}
+
+macro_rules! impl_sci_pin {
+    ($feature:literal, $port:ident, $n:literal, $pin_trait:ident, $instance:ident, $instance_feature:literal) => {
+        #[cfg(all(feature = $feature, feature = $instance_feature))]
+        impl $pin_trait<$instance> for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {}
+    };
+}
+
+// Pin assignments from the "Peripheral Select Settings" tables of the
+// RA4M2 User's Manual.
+impl_sci_pin!("port1", port1, 0, RxdPin, Sci0, "sci0"); // RXD0_B / MISO0_B / SCL0_B
+impl_sci_pin!("port1", port1, 1, TxdPin, Sci0, "sci0"); // TXD0_B / MOSI0_B / SDA0_B
+impl_sci_pin!("port1", port1, 2, SckPin, Sci0, "sci0"); // SCK0_B
+impl_sci_pin!("port1", port1, 3, CtsRtsPin, Sci0, "sci0"); // CTS0_RTS0_B / SS0_B
+impl_sci_pin!("port4", port4, 10, RxdPin, Sci0, "sci0"); // RXD0_A / MISO0_A / SCL0_A
+impl_sci_pin!("port4", port4, 11, TxdPin, Sci0, "sci0"); // TXD0_A / MOSI0_A / SDA0_A
+impl_sci_pin!("port4", port4, 12, SckPin, Sci0, "sci0"); // SCK0_A
+impl_sci_pin!("port4", port4, 13, CtsRtsPin, Sci0, "sci0"); // CTS0_RTS0_A / SS0_A
+impl_sci_pin!("port2", port2, 12, RxdPin, Sci1, "sci1"); // RXD1_B / MISO1_B / SCL1_B
+impl_sci_pin!("port2", port2, 13, TxdPin, Sci1, "sci1"); // TXD1_B / MOSI1_B / SDA1_B
+impl_sci_pin!("port1", port1, 0, SckPin, Sci1, "sci1"); // SCK1_B
+impl_sci_pin!("port1", port1, 1, CtsRtsPin, Sci1, "sci1"); // CTS1_RTS1_B / SS1_B
+impl_sci_pin!("port3", port3, 1, RxdPin, Sci2, "sci2"); // RXD2_A / MISO2_A / SCL2_A
+impl_sci_pin!("port3", port3, 2, TxdPin, Sci2, "sci2"); // TXD2_A / MOSI2_A / SDA2_A
+impl_sci_pin!("port1", port1, 13, RxdPin, Sci2, "sci2"); // RXD2_B / MISO2_B / SCL2_B
+impl_sci_pin!("port1", port1, 12, TxdPin, Sci2, "sci2"); // TXD2_B / MOSI2_B / SDA2_B
+impl_sci_pin!("port1", port1, 11, SckPin, Sci2, "sci2"); // SCK2_B
+impl_sci_pin!("port1", port1, 10, CtsRtsPin, Sci2, "sci2"); // CTS2_RTS2_B / SS2_B
+impl_sci_pin!("port4", port4, 8, RxdPin, Sci3, "sci3"); // RXD3_A / MISO3_A / SCL3_A
+impl_sci_pin!("port4", port4, 9, TxdPin, Sci3, "sci3"); // TXD3_A / MOSI3_A / SDA3_A
+impl_sci_pin!("port4", port4, 10, SckPin, Sci3, "sci3"); // SCK3_A
+impl_sci_pin!("port4", port4, 11, CtsRtsPin, Sci3, "sci3"); // CTS3_RTS3_A / SS3_A
+impl_sci_pin!("port2", port2, 6, RxdPin, Sci4, "sci4"); // RXD4_A / MISO4_A / SCL4_A
+impl_sci_pin!("port2", port2, 5, TxdPin, Sci4, "sci4"); // TXD4_A / MOSI4_A / SDA4_A
+impl_sci_pin!("port4", port4, 0, SckPin, Sci4, "sci4"); // SCK4_A
+impl_sci_pin!("port4", port4, 1, CtsRtsPin, Sci4, "sci4"); // CTS4_RTS4_A / SS4_A
+impl_sci_pin!("port1", port1, 10, RxdPin, Sci9, "sci9"); // RXD9_B / MISO9_B / SCL9_B
+impl_sci_pin!("port1", port1, 9, TxdPin, Sci9, "sci9"); // TXD9_B / MOSI9_B / SDA9_B
+impl_sci_pin!("port1", port1, 11, SckPin, Sci9, "sci9"); // SCK9_B
+impl_sci_pin!("port1", port1, 8, CtsRtsPin, Sci9, "sci9"); // CTS9_RTS9_B / SS9_B
+
+/// Picks the clock select (SMR.CKS) and bit rate register (BRR) values for
+/// `bit_rate`, given the mode's divisor at CKS = 0 (`base`, e.g. 32 for
+/// asynchronous mode with ABCS = BGDM = 0, 4 for clock synchronous mode).
+/// Each CKS step divides PCLK by a further 4. Returns `None` if the rate is
+/// out of range even at the slowest clock.
+pub(crate) fn bit_rate_setting(pclk: u32, bit_rate: u32, base: u32) -> Option<(u8, u8)> {
+    if bit_rate == 0 {
+        return None;
+    }
+
+    for cks in 0..4u8 {
+        let divisor = base as u64 * (1u64 << (2 * cks)) * bit_rate as u64;
+        // Round to nearest, then BRR = N - 1
+        let n = (pclk as u64 + divisor / 2) / divisor;
+        if n == 0 {
+            return None;
+        }
+        if n <= 256 {
+            return Some((cks, (n - 1) as u8));
+        }
+    }
+    None
+}
@@ -0,0 +1,288 @@
+//! I2C master on an SCI channel in simple IIC mode, for when both IIC
+//! channels are taken. Reports the same `I2cError`s as the IIC driver.
+//!
+//! Simple IIC mode has no bus-busy detection and no clock stretching by the
+//! master, so it suits standard-mode devices on a single-master bus.
+
+use core::marker::PhantomData;
+
+use ra4m2_pac::{sci0::{scmr::{Chr1, Sdir, Sinv, Smif}, scr::{Re, Te}, semr::Nfen, simr1::Iicm, simr2::{Iicackt, Iiccsc, Iicintm}, simr3::{Iicrstareq, Iicstareq, Iicstif, Iicstpreq}}, RegisterValue};
+
+use crate::{i2c::{I2cConfig, I2cError}, sci::{self, Instance, RxdPin, TxdPin}, sysc::SystemClock, timeout::Deadline};
+
+/// SIMR3.IICSDAS / IICSCLS settings for the SDA and SCL outputs.
+#[derive(Clone, Copy)]
+enum LineOutput {
+    /// Driven by the serial data shifter.
+    SerialData = 0,
+    /// Driven by start / restart / stop condition generation.
+    Condition = 1,
+    /// Released (high impedance).
+    HighZ = 3,
+}
+
+/// Simple IIC mode driver configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SciI2cConfig {
+    /// Bus frequency in Hz, e.g. 100_000 or 400_000.
+    pub frequency: u32,
+    /// SDA output delay (SIMR1.IICDL), in units of the SCI input clock.
+    /// Keeps SDA changes away from the falling edge of SCL.
+    pub sda_delay: u8,
+    /// Timeouts, shared with the IIC driver configuration.
+    pub i2c: I2cConfig,
+}
+
+impl Default for SciI2cConfig {
+    fn default() -> Self {
+        SciI2cConfig {
+            frequency: 100_000,
+            sda_delay: 1,
+            i2c: I2cConfig::default(),
+        }
+    }
+}
+
+/// SCI simple IIC mode master driver, generic over the SCI channel. Pins are
+/// borrowed for `'d` the same way as for `i2c::I2c`.
+pub struct SciI2c<'d, T: Instance> {
+    sci: T,
+    config: SciI2cConfig,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> SciI2c<'d, T> {
+    /// Creates the driver. SDA is the channel's TXD pin and SCL its RXD pin.
+    /// Panics if `config.frequency` can't be reached from PCLKA.
+    pub fn new<SDA: TxdPin<T>, SCL: RxdPin<T>>(
+        sci: T,
+        _sda: &'d mut SDA,
+        _scl: &'d mut SCL,
+        system_clock: &SystemClock,
+        config: SciI2cConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        // N = PCLK / (64 * 2^(2n-1) * B) - 1
+        let (cks, brr) = sci::bit_rate_setting(system_clock.get_pclka_freq(), config.frequency, 32)
+            .expect("I2C frequency out of range for PCLKA");
+
+        // See "Simple IIC Mode" initialization flowchart in the RA4M2 manual
+        unsafe {
+            let regs = T::regs();
+            regs.scr().modify(|w| w.te().set(Te::_0).re().set(Re::_0));
+
+            regs.simr3().modify(|w| {
+                w.iicsdas().set((LineOutput::HighZ as u8).into())
+                    .iicscls().set((LineOutput::HighZ as u8).into())
+            });
+
+            regs.smr().modify(|w| w.set_raw(0).cks().set(cks.into()));
+            regs.scmr().modify(|w| {
+                w.smif().set(Smif::_0).sinv().set(Sinv::_0).sdir().set(Sdir::_1).chr1().set(Chr1::_1)
+            });
+            regs.brr().modify(|w| w.set_raw(brr));
+            regs.semr().modify(|w| w.set_raw(0).nfen().set(Nfen::_1));
+            regs.snfr().modify(|w| w.nfcs().set(1.into()));
+
+            regs.simr1().modify(|w| w.iicm().set(Iicm::_1).iicdl().set(config.sda_delay.into()));
+            regs.simr2().modify(|w| {
+                w.iicintm().set(Iicintm::_1).iiccsc().set(Iiccsc::_1).iicackt().set(Iicackt::_1)
+            });
+            regs.spmr().modify(|w| w.set_raw(0));
+
+            regs.scr().modify(|w| w.te().set(Te::_1).re().set(Re::_1));
+        }
+
+        SciI2c { sci, config, _pins: PhantomData }
+    }
+
+    /// Releases the channel.
+    pub fn free(self) -> T {
+        self.sci
+    }
+
+    fn wait_for(&self, error: I2cError, condition: impl Fn(&Self) -> bool) -> Result<(), I2cError> {
+        let deadline = Deadline::after(self.config.i2c.timeout);
+        while !condition(self) {
+            if deadline.expired() {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn condition_generated(&self) -> bool {
+        unsafe {
+            T::regs().simr3().read().iicstif().get().0 == 1
+        }
+    }
+
+    fn transmit_complete(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().tend().get().0 == 1
+        }
+    }
+
+    fn is_data_received(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().rdrf().get().0 == 1
+        }
+    }
+
+    fn slave_acknowledged(&self) -> bool {
+        unsafe {
+            T::regs().sisr().read().iicackr().get().0 == 0
+        }
+    }
+
+    fn set_lines(&mut self, output: LineOutput) {
+        unsafe {
+            T::regs().simr3().modify(|w| {
+                w.iicsdas().set((output as u8).into()).iicscls().set((output as u8).into())
+            });
+        }
+    }
+
+    fn clear_condition_flag(&mut self) {
+        unsafe {
+            T::regs().simr3().modify(|w| w.iicstif().set(Iicstif::_0));
+        }
+    }
+
+    fn start(&mut self, restart: bool) -> Result<(), I2cError> {
+        self.clear_condition_flag();
+        unsafe {
+            if restart {
+                T::regs().simr3().modify(|w| w.iicrstareq().set(Iicrstareq::_1));
+            } else {
+                T::regs().simr3().modify(|w| w.iicstareq().set(Iicstareq::_1));
+            }
+        }
+        self.set_lines(LineOutput::Condition);
+        self.wait_for(I2cError::BusBusy, Self::condition_generated)?;
+        self.clear_condition_flag();
+        self.set_lines(LineOutput::SerialData);
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), I2cError> {
+        self.clear_condition_flag();
+        unsafe {
+            T::regs().simr3().modify(|w| w.iicstpreq().set(Iicstpreq::_1));
+        }
+        self.set_lines(LineOutput::Condition);
+        let result = self.wait_for(I2cError::PeripheralNotStopped, Self::condition_generated);
+        self.clear_condition_flag();
+        self.set_lines(LineOutput::HighZ);
+        result
+    }
+
+    /// Shifts one byte out (or 0xFF to clock one in) and returns once the
+    /// ninth (ACK) clock has completed.
+    fn shift(&mut self, byte: u8) -> Result<(), I2cError> {
+        unsafe {
+            T::regs().tdr().modify(|w| w.set_raw(byte));
+        }
+        self.wait_for(I2cError::TransmitBufferNotReady, Self::transmit_complete)
+    }
+
+    fn write_address(&mut self, address: u8, read: bool) -> Result<(), I2cError> {
+        self.shift((address << 1) | read as u8)?;
+        if self.slave_acknowledged() {
+            Ok(())
+        } else {
+            Err(I2cError::SlaveNotResponding)
+        }
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) -> Result<(), I2cError> {
+        for byte in data {
+            self.shift(*byte)?;
+            if !self.slave_acknowledged() {
+                return Err(I2cError::DataNotReceived);
+            }
+        }
+        Ok(())
+    }
+
+    /// Clocks in `buffer.len()` bytes, ACKing each one except the last when
+    /// `nack_last` is set (the end of a run of reads).
+    fn read_bytes(&mut self, buffer: &mut [u8], nack_last: bool) -> Result<(), I2cError> {
+        if buffer.is_empty() {
+            // At least one byte has to be clocked in the read direction
+            return self.read_bytes(&mut [0u8; 1], nack_last);
+        }
+
+        let last = buffer.len() - 1;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            let ackt = if nack_last && i == last { Iicackt::_1 } else { Iicackt::_0 };
+            unsafe {
+                T::regs().simr2().modify(|w| w.iicackt().set(ackt));
+            }
+            self.shift(0xFF)?;
+            self.wait_for(I2cError::DataNotReceived, Self::is_data_received)?;
+            *byte = unsafe { T::regs().rdr().read().get_raw() };
+        }
+        unsafe {
+            T::regs().simr2().modify(|w| w.iicackt().set(Iicackt::_1));
+        }
+        Ok(())
+    }
+
+    /// Runs the operations as one transaction: a start, a restart between
+    /// operations of different direction, and a single stop at the end.
+    fn run(&mut self, address: u8, operations: &mut [embedded_hal::i2c::Operation<'_>]) -> Result<(), I2cError> {
+        let mut previous_read = None;
+        for i in 0..operations.len() {
+            let is_read = matches!(operations[i], embedded_hal::i2c::Operation::Read(_));
+            let next_read = operations
+                .get(i + 1)
+                .map(|next| matches!(next, embedded_hal::i2c::Operation::Read(_)));
+
+            if previous_read != Some(is_read) {
+                self.start(previous_read.is_some())?;
+                self.write_address(address, is_read)?;
+            }
+            match &mut operations[i] {
+                embedded_hal::i2c::Operation::Write(data) => self.write_bytes(data)?,
+                embedded_hal::i2c::Operation::Read(buffer) => self.read_bytes(buffer, next_read != Some(true))?,
+            }
+            previous_read = Some(is_read);
+        }
+        Ok(())
+    }
+
+    /// Writes data to the I2C slave device at the specified address.
+    pub fn write(&mut self, address: u8, data: &[u8]) -> Result<(), I2cError> {
+        self.transaction_inner(address, &mut [embedded_hal::i2c::Operation::Write(data)])
+    }
+
+    /// Reads `buffer.len()` bytes from the I2C slave device at the specified
+    /// address.
+    pub fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.transaction_inner(address, &mut [embedded_hal::i2c::Operation::Read(buffer)])
+    }
+
+    fn transaction_inner(&mut self, address: u8, operations: &mut [embedded_hal::i2c::Operation<'_>]) -> Result<(), I2cError> {
+        if operations.is_empty() {
+            return Ok(());
+        }
+        let result = self.run(address, operations);
+        // Always release the bus, but report the first error
+        let stopped = self.stop();
+        result.and(stopped)
+    }
+}
+
+impl<'d, T: Instance> embedded_hal::i2c::ErrorType for SciI2c<'d, T> {
+    type Error = I2cError;
+}
+
+impl<'d, T: Instance> embedded_hal::i2c::I2c<embedded_hal::i2c::SevenBitAddress> for SciI2c<'d, T> {
+    fn transaction(&mut self, address: u8, operations: &mut [embedded_hal::i2c::Operation<'_>]) -> Result<(), Self::Error> {
+        self.transaction_inner(address, operations)
+    }
+}
@@ -0,0 +1,200 @@
+//! SPI master on an SCI channel in simple SPI (clock synchronous) mode, for
+//! devices on pins that only have SCI functions.
+//!
+//! Chip select is left to the caller (a GPIO, e.g. through
+//! `embedded_hal_bus::spi::ExclusiveDevice`); the SCI's SSn input is only used
+//! in slave mode. Reports the same `SpiError`s as the SPI driver.
+
+use core::marker::PhantomData;
+
+use embassy_time::Duration;
+use embedded_hal::spi::{Phase, Polarity};
+use ra4m2_pac::{sci0::{scmr::{Chr1, Sdir, Sinv, Smif}, scr::{Re, Te}, simr1::Iicm, smr::{Chr, Cm}, spmr::{Ckph, Ckpol, Mss, Sse}, ssr::Orer}, RegisterValue};
+
+use crate::{sci::{self, Instance, RxdPin, SckPin, TxdPin}, spi::{BitOrder, SpiError}, sysc::SystemClock, timeout::Deadline};
+
+/// Simple SPI mode driver configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SciSpiConfig {
+    /// SCK frequency in Hz, rounded to the nearest rate the divider allows.
+    pub frequency: u32,
+    pub mode: embedded_hal::spi::Mode,
+    pub bit_order: BitOrder,
+    /// How long each byte, and `flush`, may take before it is reported as
+    /// `SpiError::Timeout`. Measured in wall-clock time like `I2cConfig`'s.
+    pub timeout: Duration,
+}
+
+impl Default for SciSpiConfig {
+    fn default() -> Self {
+        SciSpiConfig {
+            frequency: 1_000_000,
+            mode: embedded_hal::spi::MODE_0,
+            bit_order: BitOrder::MsbFirst,
+            timeout: Duration::from_millis(10),
+        }
+    }
+}
+
+/// SCI simple SPI master driver, generic over the SCI channel. MOSI is the
+/// channel's TXD pin and MISO its RXD pin; pins are borrowed for `'d` the same
+/// way as for `i2c::I2c`.
+pub struct SciSpi<'d, T: Instance> {
+    sci: T,
+    config: SciSpiConfig,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> SciSpi<'d, T> {
+    /// Creates the driver. Panics if `config.frequency` can't be reached from
+    /// PCLKA.
+    pub fn new<MOSI: TxdPin<T>, MISO: RxdPin<T>, SCK: SckPin<T>>(
+        sci: T,
+        _mosi: &'d mut MOSI,
+        _miso: &'d mut MISO,
+        _sck: &'d mut SCK,
+        system_clock: &SystemClock,
+        config: SciSpiConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        // N = PCLK / (8 * 2^(2n-1) * B) - 1
+        let (cks, brr) = sci::bit_rate_setting(system_clock.get_pclka_freq(), config.frequency, 4)
+            .expect("SPI frequency out of range for PCLKA");
+
+        // CKPH delays the clock by half a cycle, which samples on the first
+        // edge: SPI phase 0.
+        let ckph = match config.mode.phase {
+            Phase::CaptureOnFirstTransition => Ckph::_1,
+            Phase::CaptureOnSecondTransition => Ckph::_0,
+        };
+        let ckpol = match config.mode.polarity {
+            Polarity::IdleLow => Ckpol::_0,
+            Polarity::IdleHigh => Ckpol::_1,
+        };
+        let sdir = match config.bit_order {
+            BitOrder::MsbFirst => Sdir::_1,
+            BitOrder::LsbFirst => Sdir::_0,
+        };
+
+        // See "Simple SPI Mode" initialization flowchart in the RA4M2 manual
+        unsafe {
+            let regs = T::regs();
+            // CKE = 0: internal clock, output on SCK
+            regs.scr().modify(|w| w.set_raw(0));
+
+            regs.simr1().modify(|w| w.iicm().set(Iicm::_0));
+            regs.spmr().modify(|w| {
+                w.set_raw(0).sse().set(Sse::_0).mss().set(Mss::_0).ckph().set(ckph).ckpol().set(ckpol)
+            });
+            regs.smr().modify(|w| w.set_raw(0).cm().set(Cm::_1).chr().set(Chr::_0).cks().set(cks.into()));
+            regs.scmr().modify(|w| {
+                w.smif().set(Smif::_0).sinv().set(Sinv::_0).sdir().set(sdir).chr1().set(Chr1::_1)
+            });
+            regs.brr().modify(|w| w.set_raw(brr));
+            regs.semr().modify(|w| w.set_raw(0));
+
+            // TE and RE must be set in a single write
+            regs.scr().modify(|w| w.te().set(Te::_1).re().set(Re::_1));
+        }
+
+        SciSpi { sci, config, _pins: PhantomData }
+    }
+
+    /// Releases the channel.
+    pub fn free(self) -> T {
+        self.sci
+    }
+
+    /// Busy-waits until `condition` holds, failing with `SpiError::Timeout`
+    /// once the configured timeout has elapsed.
+    fn wait_for(&self, condition: impl Fn(&Self) -> bool) -> Result<(), SpiError> {
+        let deadline = Deadline::after(self.config.timeout);
+        while !condition(self) {
+            if deadline.expired() {
+                return Err(SpiError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn transmit_buffer_ready(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().tdre().get().0 == 1
+        }
+    }
+
+    fn is_data_received(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().rdrf().get().0 == 1
+        }
+    }
+
+    fn transmit_complete(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().tend().get().0 == 1
+        }
+    }
+
+    /// Shifts one byte out and returns the byte clocked in at the same time.
+    fn transfer_byte(&mut self, byte: u8) -> Result<u8, SpiError> {
+        self.wait_for(Self::transmit_buffer_ready)?;
+        unsafe {
+            T::regs().tdr().modify(|w| w.set_raw(byte));
+        }
+        self.wait_for(Self::is_data_received)?;
+
+        unsafe {
+            let received = T::regs().rdr().read().get_raw();
+            if T::regs().ssr().read().orer().get().0 == 1 {
+                T::regs().ssr().modify(|w| w.orer().set(Orer::_0));
+                return Err(SpiError::Overrun);
+            }
+            Ok(received)
+        }
+    }
+}
+
+impl<'d, T: Instance> embedded_hal::spi::ErrorType for SciSpi<'d, T> {
+    type Error = SpiError;
+}
+
+impl<'d, T: Instance> embedded_hal::spi::SpiBus<u8> for SciSpi<'d, T> {
+    /// Clocks out 0xFF while reading.
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for word in words {
+            *word = self.transfer_byte(0xFF)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        for word in words {
+            self.transfer_byte(*word)?;
+        }
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        for i in 0..read.len().max(write.len()) {
+            let received = self.transfer_byte(write.get(i).copied().unwrap_or(0xFF))?;
+            if let Some(word) = read.get_mut(i) {
+                *word = received;
+            }
+        }
+        Ok(())
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        for word in words {
+            *word = self.transfer_byte(*word)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.wait_for(Self::transmit_complete)
+    }
+}
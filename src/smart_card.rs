@@ -0,0 +1,481 @@
+//! ISO 7816-3 smart card interface on an SCI channel (SIM cards, secure
+//! elements), T=0 character level.
+//!
+//! The card's I/O line connects to both the channel's TXD pin (muxed
+//! open-drain) and its RXD pin, CLK to the SCK pin, and RST to any GPIO. The
+//! SCI generates the card clock, and handles the T=0 error signal in both
+//! directions: a character received with bad parity is answered with an
+//! error signal so the card repeats it, and a character the card rejects is
+//! sent again by the hardware. The driver only counts the repetitions.
+//!
+//! Protocol and parameter selection (PPS) is left to the caller; after a
+//! successful PPS exchange apply the new divisor with `set_clocks_per_etu`.
+
+use core::{convert::Infallible, marker::PhantomData};
+
+use embassy_time::Duration;
+use ra4m2_pac::{sci0::{scmr::{Sdir, Sinv, Smif}, scr_smci::{Re, Te}, smr_smci::{Blk, Gm, Pe, Pm}, ssr_smci::{Ers, Orer, Per}}, RegisterValue};
+
+use crate::{sci::{self, Instance, RxdPin, SckPin, TxdPin}, sysc::SystemClock, timeout::Deadline};
+
+/// Maximum length of an answer to reset, TS included.
+pub const ATR_MAX: usize = 33;
+
+/// Times a character is repeated after an error signal before giving up.
+const MAX_REPETITIONS: u8 = 4;
+
+/// Card clock cycles from RST high until the first ATR character at the
+/// latest (ISO 7816-3: 40 000).
+const ATR_START_CLOCKS: u64 = 40_000;
+/// Card clock cycles RST is held low on a cold reset (at least 400).
+const RESET_CLOCKS: u64 = 500;
+/// Default T=0 waiting time between characters, in ETU (960 * WI, WI = 10).
+const DEFAULT_WAITING_ETU: u64 = 9_600;
+
+/// Smart card interface configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmartCardConfig {
+    /// Card clock on SCK in Hz (1 to 5 MHz for most cards).
+    pub clock_frequency: u32,
+    /// Card clock cycles per elementary time unit, F / D. 372 before PPS.
+    pub clocks_per_etu: u16,
+    /// Extra guard time added after each transmitted character, in ETU (N
+    /// from TC1).
+    pub extra_guard_time: u8,
+}
+
+impl Default for SmartCardConfig {
+    fn default() -> Self {
+        SmartCardConfig {
+            clock_frequency: 4_000_000,
+            clocks_per_etu: 372,
+            extra_guard_time: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SmartCardError {
+    /// The card didn't send the next character within the waiting time.
+    Timeout,
+    /// A received character still had bad parity after the card repeated it
+    /// the maximum number of times.
+    Parity,
+    /// The card kept signalling errors for a transmitted character.
+    Rejected,
+    /// Receive overrun.
+    Overrun,
+    /// The answer to reset is malformed.
+    InvalidAtr,
+    /// `clocks_per_etu` isn't one of the base clock ratios the SCI supports.
+    UnsupportedEtu,
+}
+
+/// Bit order and level convention, announced by TS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Convention {
+    /// TS = 0x3B: LSB first, high level is 1.
+    Direct,
+    /// TS = 0x3F: MSB first, low level is 1.
+    Inverse,
+}
+
+/// Answer to reset, with the interface bytes the driver cares about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Atr {
+    bytes: [u8; ATR_MAX],
+    len: usize,
+    historical_start: usize,
+    historical_len: usize,
+    pub convention: Convention,
+    /// TA1: Fi (high nibble) and Di (low nibble) indices.
+    pub ta1: Option<u8>,
+    /// TC1: extra guard time N.
+    pub tc1: Option<u8>,
+    /// Bit n set if protocol T=n is offered. T=0 if no TD1 is present.
+    pub protocols: u16,
+}
+
+impl Atr {
+    /// The raw answer to reset, TS first, as decoded under its convention.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn historical_bytes(&self) -> &[u8] {
+        &self.bytes[self.historical_start..self.historical_start + self.historical_len]
+    }
+}
+
+/// SCI base clock cycles per ETU selectable with SCMR.BCP2 and SMR.BCP.
+fn base_clock_setting(clocks_per_etu: u16) -> Option<(u8, u8)> {
+    // (S, BCP2, BCP)
+    const SETTINGS: [(u16, u8, u8); 8] = [
+        (32, 1, 0b00),
+        (64, 1, 0b01),
+        (372, 1, 0b10),
+        (256, 1, 0b11),
+        (93, 0, 0b00),
+        (128, 0, 0b01),
+        (186, 0, 0b10),
+        (512, 0, 0b11),
+    ];
+    SETTINGS
+        .iter()
+        .find(|(s, _, _)| *s == clocks_per_etu)
+        .map(|(_, bcp2, bcp)| (*bcp2, *bcp))
+}
+
+/// Smart card interface driver, generic over the SCI channel. Pins are
+/// borrowed for `'d` the same way as for `i2c::I2c`.
+pub struct SmartCard<'d, T: Instance, RST: embedded_hal::digital::OutputPin<Error = Infallible>> {
+    sci: T,
+    rst: &'d mut RST,
+    /// Actual card clock frequency.
+    clock_hz: u32,
+    clocks_per_etu: u16,
+    extra_guard_time: u8,
+    waiting_etu: u64,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance, RST: embedded_hal::digital::OutputPin<Error = Infallible>> SmartCard<'d, T, RST> {
+    /// Sets up the channel and starts the card clock, with RST held low.
+    /// Mux `io_tx` open-drain. Panics if the clock frequency can't be
+    /// generated from PCLKA or `clocks_per_etu` isn't supported.
+    pub fn new<IOTX: TxdPin<T>, IORX: RxdPin<T>, CLK: SckPin<T>>(
+        sci: T,
+        _io_tx: &'d mut IOTX,
+        _io_rx: &'d mut IORX,
+        _clk: &'d mut CLK,
+        rst: &'d mut RST,
+        system_clock: &SystemClock,
+        config: SmartCardConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        let _ = rst.set_low();
+
+        // f = PCLK / (2 * 2^(2n) * (N + 1))
+        let pclk = system_clock.get_pclka_freq();
+        let (cks, brr) = sci::bit_rate_setting(pclk, config.clock_frequency, 2)
+            .expect("Card clock out of range for PCLKA");
+        let (bcp2, bcp) = base_clock_setting(config.clocks_per_etu).expect("Unsupported clocks per ETU");
+
+        // See "Smart Card Interface Mode" initialization flowchart in the
+        // RA4M2 manual
+        unsafe {
+            let regs = T::regs();
+            regs.scr_smci().modify(|w| w.set_raw(0));
+
+            regs.simr1().modify(|w| w.set_raw(0));
+            regs.spmr().modify(|w| w.set_raw(0));
+
+            // GM = 0: 12 ETU character frame with error signalling (T=0).
+            // Parity is always even in smart card mode.
+            regs.smr_smci().modify(|w| {
+                w.gm().set(Gm::_0).blk().set(Blk::_0).pe().set(Pe::_1).pm().set(Pm::_0)
+                    .bcp().set(bcp.into()).cks().set(cks.into())
+            });
+            regs.scmr().modify(|w| {
+                w.smif().set(Smif::_1).sinv().set(Sinv::_0).sdir().set(Sdir::_0).bcp2().set(bcp2.into())
+            });
+            regs.brr().modify(|w| w.set_raw(brr));
+            regs.semr().modify(|w| w.set_raw(0));
+
+            // CKE = 01: clock output on SCK
+            regs.scr_smci().modify(|w| w.cke().set(1.into()));
+        }
+
+        let mut card = SmartCard {
+            sci,
+            rst,
+            clock_hz: pclk / (2 * (1 << (2 * cks)) * (brr as u32 + 1)),
+            clocks_per_etu: config.clocks_per_etu,
+            extra_guard_time: config.extra_guard_time,
+            waiting_etu: DEFAULT_WAITING_ETU,
+            _pins: PhantomData,
+        };
+        card.receive_mode();
+        card
+    }
+
+    /// Stops the clock and releases the channel. RST is left low.
+    pub fn free(mut self) -> T {
+        let _ = self.rst.set_low();
+        unsafe {
+            T::regs().scr_smci().modify(|w| w.set_raw(0));
+        }
+        self.sci
+    }
+
+    fn clocks(&self, clocks: u64) -> Duration {
+        Duration::from_micros((clocks * 1_000_000).div_ceil(self.clock_hz as u64))
+    }
+
+    fn etus(&self, etus: u64) -> Duration {
+        self.clocks(etus * self.clocks_per_etu as u64)
+    }
+
+    /// Switches the base clock ratio, e.g. after PPS negotiated F and D.
+    pub fn set_clocks_per_etu(&mut self, clocks_per_etu: u16) -> Result<(), SmartCardError> {
+        let (bcp2, bcp) = base_clock_setting(clocks_per_etu).ok_or(SmartCardError::UnsupportedEtu)?;
+        self.while_stopped(|regs| unsafe {
+            regs.smr_smci().modify(|w| w.bcp().set(bcp.into()));
+            regs.scmr().modify(|w| w.bcp2().set(bcp2.into()));
+        });
+        self.clocks_per_etu = clocks_per_etu;
+        Ok(())
+    }
+
+    /// Sets the extra guard time after each transmitted character, in ETU.
+    pub fn set_extra_guard_time(&mut self, etus: u8) {
+        self.extra_guard_time = etus;
+    }
+
+    /// Sets the T=0 waiting time between characters, in ETU (960 * WI).
+    pub fn set_waiting_time(&mut self, etus: u64) {
+        self.waiting_etu = etus;
+    }
+
+    /// Runs `f` with TE and RE cleared, as SCMR and SMR can only be written
+    /// then. The clock keeps running.
+    fn while_stopped(&mut self, f: impl FnOnce(&ra4m2_pac::Sci0)) {
+        unsafe {
+            let regs = T::regs();
+            regs.scr_smci().modify(|w| w.te().set(Te::_0).re().set(Re::_0));
+            f(&regs);
+        }
+        self.receive_mode();
+    }
+
+    // The line is half duplex; only one of TE and RE is on at a time, each
+    // switch going through TE = RE = 0.
+    fn receive_mode(&mut self) {
+        unsafe {
+            let regs = T::regs();
+            if regs.scr_smci().read().re().get().0 == 1 {
+                return;
+            }
+            regs.scr_smci().modify(|w| w.te().set(Te::_0).re().set(Re::_0));
+            regs.scr_smci().modify(|w| w.re().set(Re::_1));
+        }
+    }
+
+    fn transmit_mode(&mut self) {
+        unsafe {
+            let regs = T::regs();
+            if regs.scr_smci().read().te().get().0 == 1 {
+                return;
+            }
+            regs.scr_smci().modify(|w| w.te().set(Te::_0).re().set(Re::_0));
+            regs.scr_smci().modify(|w| w.te().set(Te::_1));
+        }
+    }
+
+    /// Receives one character, waiting up to `timeout`. Characters with bad
+    /// parity have already been answered with an error signal by the
+    /// hardware; the card repeats them.
+    fn receive_byte(&mut self, timeout: Duration) -> Result<u8, SmartCardError> {
+        self.receive_mode();
+        let mut repetitions = 0;
+        let mut deadline = Deadline::after(timeout);
+        loop {
+            let ssr = unsafe { T::regs().ssr_smci().read() };
+            if ssr.orer().get().0 == 1 {
+                unsafe {
+                    T::regs().ssr_smci().modify(|w| w.orer().set(Orer::_0));
+                }
+                return Err(SmartCardError::Overrun);
+            }
+            if ssr.per().get().0 == 1 {
+                unsafe {
+                    let _ = T::regs().rdr().read();
+                    T::regs().ssr_smci().modify(|w| w.per().set(Per::_0));
+                }
+                repetitions += 1;
+                if repetitions > MAX_REPETITIONS {
+                    return Err(SmartCardError::Parity);
+                }
+                deadline = Deadline::after(timeout);
+                continue;
+            }
+            if ssr.rdrf().get().0 == 1 {
+                return Ok(unsafe { T::regs().rdr().read().get_raw() });
+            }
+            if deadline.expired() {
+                return Err(SmartCardError::Timeout);
+            }
+        }
+    }
+
+    /// Sends one character and waits out its error signal window. The
+    /// hardware repeats the character itself when the card signals an error.
+    fn transmit_byte(&mut self, byte: u8) -> Result<(), SmartCardError> {
+        self.transmit_mode();
+        unsafe {
+            T::regs().tdr().modify(|w| w.set_raw(byte));
+        }
+
+        let mut repetitions = 0;
+        let mut deadline = Deadline::after(self.etus(24));
+        loop {
+            let ssr = unsafe { T::regs().ssr_smci().read() };
+            if ssr.ers().get().0 == 1 {
+                unsafe {
+                    T::regs().ssr_smci().modify(|w| w.ers().set(Ers::_0));
+                }
+                repetitions += 1;
+                if repetitions > MAX_REPETITIONS {
+                    return Err(SmartCardError::Rejected);
+                }
+                deadline = Deadline::after(self.etus(24));
+            } else if ssr.tend().get().0 == 1 {
+                break;
+            } else if deadline.expired() {
+                return Err(SmartCardError::Timeout);
+            }
+        }
+
+        if self.extra_guard_time > 0 {
+            let guard = Deadline::after(self.etus(self.extra_guard_time as u64));
+            while !guard.expired() {}
+        }
+        Ok(())
+    }
+
+    /// Sends `data`, character by character.
+    pub fn transmit(&mut self, data: &[u8]) -> Result<(), SmartCardError> {
+        for byte in data {
+            self.transmit_byte(*byte)?;
+        }
+        self.receive_mode();
+        Ok(())
+    }
+
+    /// Receives exactly `buffer.len()` characters, each within the waiting
+    /// time of the previous one.
+    pub fn receive(&mut self, buffer: &mut [u8]) -> Result<(), SmartCardError> {
+        let timeout = self.etus(self.waiting_etu);
+        for byte in buffer {
+            *byte = self.receive_byte(timeout)?;
+        }
+        Ok(())
+    }
+
+    fn next_atr_byte(&mut self, bytes: &mut [u8; ATR_MAX], len: &mut usize) -> Result<u8, SmartCardError> {
+        if *len == ATR_MAX {
+            return Err(SmartCardError::InvalidAtr);
+        }
+        let byte = self.receive_byte(self.etus(self.waiting_etu))?;
+        bytes[*len] = byte;
+        *len += 1;
+        Ok(byte)
+    }
+
+    /// Cold reset: holds RST low, releases it and reads the answer to reset.
+    /// The convention TS announces is applied to all later characters, and
+    /// TC1 becomes the extra guard time.
+    pub fn reset(&mut self) -> Result<Atr, SmartCardError> {
+        // Start from the direct convention; an inverse convention TS reads
+        // as 0x03 under it
+        self.while_stopped(|regs| unsafe {
+            regs.scmr().modify(|w| w.sinv().set(Sinv::_0).sdir().set(Sdir::_0));
+        });
+
+        let _ = self.rst.set_low();
+        let hold = Deadline::after(self.clocks(RESET_CLOCKS));
+        while !hold.expired() {}
+        unsafe {
+            // Discard anything latched while the card was in reset
+            let _ = T::regs().rdr().read();
+            T::regs().ssr_smci().modify(|w| w.orer().set(Orer::_0).ers().set(Ers::_0).per().set(Per::_0));
+        }
+        let _ = self.rst.set_high();
+
+        let mut bytes = [0u8; ATR_MAX];
+        bytes[0] = self.receive_byte(self.clocks(ATR_START_CLOCKS))?;
+        let convention = match bytes[0] {
+            0x3B => Convention::Direct,
+            0x03 => Convention::Inverse,
+            _ => return Err(SmartCardError::InvalidAtr),
+        };
+        if convention == Convention::Inverse {
+            self.while_stopped(|regs| unsafe {
+                regs.scmr().modify(|w| w.sinv().set(Sinv::_1).sdir().set(Sdir::_1));
+            });
+            bytes[0] = 0x3F;
+        }
+
+        let mut len = 1;
+        let t0 = self.next_atr_byte(&mut bytes, &mut len)?;
+        let historical_len = (t0 & 0x0F) as usize;
+        let mut indicator = t0 >> 4;
+        let mut ta1 = None;
+        let mut tc1 = None;
+        let mut protocols = 0u16;
+        let mut offered_protocol = false;
+        let mut group = 1;
+        loop {
+            if indicator & 0x1 != 0 {
+                let ta = self.next_atr_byte(&mut bytes, &mut len)?;
+                if group == 1 {
+                    ta1 = Some(ta);
+                }
+            }
+            if indicator & 0x2 != 0 {
+                self.next_atr_byte(&mut bytes, &mut len)?;
+            }
+            if indicator & 0x4 != 0 {
+                let tc = self.next_atr_byte(&mut bytes, &mut len)?;
+                if group == 1 {
+                    tc1 = Some(tc);
+                }
+            }
+            if indicator & 0x8 == 0 {
+                break;
+            }
+            let td = self.next_atr_byte(&mut bytes, &mut len)?;
+            protocols |= 1 << (td & 0x0F);
+            offered_protocol = true;
+            indicator = td >> 4;
+            group += 1;
+        }
+        if !offered_protocol {
+            protocols = 1;
+        }
+
+        let historical_start = len;
+        for _ in 0..historical_len {
+            self.next_atr_byte(&mut bytes, &mut len)?;
+        }
+
+        // TCK is present unless only T=0 is offered, and XORs to zero with
+        // everything after TS
+        if protocols & !1 != 0 {
+            self.next_atr_byte(&mut bytes, &mut len)?;
+            if bytes[1..len].iter().fold(0, |check, byte| check ^ byte) != 0 {
+                return Err(SmartCardError::InvalidAtr);
+            }
+        }
+
+        // TC1 = 255 means the minimum guard time, which GM = 0 already gives
+        self.extra_guard_time = match tc1 {
+            Some(n) if n != 255 => n,
+            _ => 0,
+        };
+
+        Ok(Atr {
+            bytes,
+            len,
+            historical_start,
+            historical_len,
+            convention,
+            ta1,
+            tc1,
+            protocols,
+        })
+    }
+}
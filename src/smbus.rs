@@ -0,0 +1,250 @@
+//! SMBus / PMBus protocol layer on top of any `embedded_hal::i2c::I2c` bus,
+//! typically `I2c0` or `I2c1` created with `I2cConfig { smbus: true, .. }` so
+//! the IIC block's hardware timeout detection is active.
+//!
+//! Packet error checking (PEC) is optional and covers every byte on the wire,
+//! including the address bytes, as required by SMBus 2.0 section 5.4.3.
+//!
+//! Block reads end at the length given by the device's count byte, which a
+//! plain `transaction` can't express; they need a bus that also implements
+//! `CountedRead`, such as this crate's `I2c`.
+
+use embedded_hal::i2c::{ErrorType, I2c, Operation, SevenBitAddress};
+
+/// Largest block transfer allowed by SMBus 3.0 / PMBus 1.3.
+pub const BLOCK_MAX: usize = 255;
+
+/// SMBus Alert Response Address, read by the host after SMBALERT# asserts.
+pub const ALERT_RESPONSE_ADDRESS: u8 = 0x0C;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SmBusError<E> {
+    /// Error from the underlying I2C bus.
+    I2c(E),
+    /// The PEC byte received didn't match the one computed locally.
+    Pec,
+    /// A block was longer than `BLOCK_MAX` or the caller's buffer.
+    BlockLength,
+}
+
+impl<E> From<E> for SmBusError<E> {
+    fn from(error: E) -> Self {
+        SmBusError::I2c(error)
+    }
+}
+
+/// CRC-8 (polynomial x^8 + x^2 + x + 1) used for SMBus packet error checking.
+pub fn crc8(crc: u8, data: &[u8]) -> u8 {
+    let mut crc = crc;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn write_address(address: u8) -> u8 {
+    address << 1
+}
+
+fn read_address(address: u8) -> u8 {
+    (address << 1) | 1
+}
+
+/// I2C buses that can end a read at a length given by its first byte, as
+/// the SMBus block read requires.
+pub trait CountedRead: ErrorType {
+    /// See `I2c::write_read_counted`.
+    fn write_read_counted(&mut self, address: u8, write: &[u8], frame: &mut [u8], trailing: usize) -> Result<usize, Self::Error>;
+}
+
+impl<'d, T: crate::i2c::Instance, SDA: crate::i2c::I2cSDAPin<T>, SCL: crate::i2c::I2cSCLPin<T>> CountedRead for crate::i2c::I2c<'d, T, SDA, SCL> {
+    fn write_read_counted(&mut self, address: u8, write: &[u8], frame: &mut [u8], trailing: usize) -> Result<usize, Self::Error> {
+        crate::i2c::I2c::write_read_counted(self, address, write, frame, trailing)
+    }
+}
+
+/// SMBus host built on an I2C bus.
+pub struct SmBus<I2C> {
+    i2c: I2C,
+    pec: bool,
+}
+
+impl<I2C: I2c<SevenBitAddress>> SmBus<I2C> {
+    /// Wraps `i2c`. With `pec` set, every command appends or checks a PEC byte.
+    pub fn new(i2c: I2C, pec: bool) -> Self {
+        SmBus { i2c, pec }
+    }
+
+    /// Returns the underlying bus.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Enables or disables packet error checking.
+    pub fn set_pec(&mut self, pec: bool) {
+        self.pec = pec;
+    }
+
+    /// Quick Command: the address byte with the write bit, no data.
+    pub fn quick_command(&mut self, address: u8) -> Result<(), SmBusError<I2C::Error>> {
+        self.i2c.write(address, &[])?;
+        Ok(())
+    }
+
+    /// Send Byte: a single data byte with no command code.
+    pub fn send_byte(&mut self, address: u8, byte: u8) -> Result<(), SmBusError<I2C::Error>> {
+        let pec = crc8(0, &[write_address(address), byte]);
+        if self.pec {
+            self.i2c.write(address, &[byte, pec])?;
+        } else {
+            self.i2c.write(address, &[byte])?;
+        }
+        Ok(())
+    }
+
+    /// Receive Byte: a single data byte with no command code.
+    pub fn receive_byte(&mut self, address: u8) -> Result<u8, SmBusError<I2C::Error>> {
+        let mut buffer = [0u8; 2];
+        let len = if self.pec { 2 } else { 1 };
+        self.i2c.read(address, &mut buffer[..len])?;
+
+        if self.pec && crc8(0, &[read_address(address), buffer[0]]) != buffer[1] {
+            return Err(SmBusError::Pec);
+        }
+        Ok(buffer[0])
+    }
+
+    /// Write Byte: command code followed by one data byte.
+    pub fn write_byte(&mut self, address: u8, command: u8, byte: u8) -> Result<(), SmBusError<I2C::Error>> {
+        self.write_command(address, command, &[byte])
+    }
+
+    /// Write Word: command code followed by a little-endian 16-bit word.
+    pub fn write_word(&mut self, address: u8, command: u8, word: u16) -> Result<(), SmBusError<I2C::Error>> {
+        self.write_command(address, command, &word.to_le_bytes())
+    }
+
+    /// Read Byte: command code, then one data byte read back.
+    pub fn read_byte(&mut self, address: u8, command: u8) -> Result<u8, SmBusError<I2C::Error>> {
+        let mut data = [0u8; 1];
+        self.read_command(address, command, &mut data)?;
+        Ok(data[0])
+    }
+
+    /// Read Word: command code, then a little-endian 16-bit word read back.
+    pub fn read_word(&mut self, address: u8, command: u8) -> Result<u16, SmBusError<I2C::Error>> {
+        let mut data = [0u8; 2];
+        self.read_command(address, command, &mut data)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    /// Process Call: writes a word and reads a word back in one transaction.
+    pub fn process_call(&mut self, address: u8, command: u8, word: u16) -> Result<u16, SmBusError<I2C::Error>> {
+        let out = word.to_le_bytes();
+        let mut buffer = [0u8; 3];
+        let len = if self.pec { 3 } else { 2 };
+
+        self.i2c.transaction(address, &mut [
+            Operation::Write(&[command, out[0], out[1]]),
+            Operation::Read(&mut buffer[..len]),
+        ])?;
+
+        if self.pec {
+            let crc = crc8(0, &[write_address(address), command, out[0], out[1], read_address(address)]);
+            if crc8(crc, &buffer[..2]) != buffer[2] {
+                return Err(SmBusError::Pec);
+            }
+        }
+        Ok(u16::from_le_bytes([buffer[0], buffer[1]]))
+    }
+
+    /// Block Write: command code, byte count, then `data`.
+    pub fn block_write(&mut self, address: u8, command: u8, data: &[u8]) -> Result<(), SmBusError<I2C::Error>> {
+        if data.len() > BLOCK_MAX {
+            return Err(SmBusError::BlockLength);
+        }
+
+        let mut frame = [0u8; BLOCK_MAX + 1];
+        frame[0] = data.len() as u8;
+        frame[1..=data.len()].copy_from_slice(data);
+        self.write_command(address, command, &frame[..=data.len()])
+    }
+
+    /// Reads the Alert Response Address after SMBALERT# asserts and returns
+    /// the 7-bit address of the device that raised the alert.
+    pub fn alert_response(&mut self) -> Result<u8, SmBusError<I2C::Error>> {
+        let byte = self.receive_byte(ALERT_RESPONSE_ADDRESS)?;
+        Ok(byte >> 1)
+    }
+
+    fn write_command(&mut self, address: u8, command: u8, data: &[u8]) -> Result<(), SmBusError<I2C::Error>> {
+        // command + data + PEC
+        let mut frame = [0u8; BLOCK_MAX + 3];
+        frame[0] = command;
+        frame[1..=data.len()].copy_from_slice(data);
+        let mut len = data.len() + 1;
+
+        if self.pec {
+            let crc = crc8(0, &[write_address(address)]);
+            frame[len] = crc8(crc, &frame[..len]);
+            len += 1;
+        }
+
+        self.i2c.write(address, &frame[..len])?;
+        Ok(())
+    }
+
+    fn read_command(&mut self, address: u8, command: u8, data: &mut [u8]) -> Result<(), SmBusError<I2C::Error>> {
+        // data + PEC
+        let mut frame = [0u8; 3];
+        let len = data.len() + self.pec as usize;
+        self.i2c.transaction(address, &mut [
+            Operation::Write(&[command]),
+            Operation::Read(&mut frame[..len]),
+        ])?;
+
+        if self.pec {
+            let crc = crc8(0, &[write_address(address), command, read_address(address)]);
+            if crc8(crc, &frame[..data.len()]) != frame[data.len()] {
+                return Err(SmBusError::Pec);
+            }
+        }
+
+        data.copy_from_slice(&frame[..data.len()]);
+        Ok(())
+    }
+}
+
+impl<I2C: I2c<SevenBitAddress> + CountedRead> SmBus<I2C> {
+    /// Block Read: command code, then a byte count and that many data bytes.
+    /// Returns the number of bytes placed in `buffer`.
+    ///
+    /// The count byte sets the read length, so the device is NACKed right
+    /// after its last data byte (or its PEC byte).
+    pub fn block_read(&mut self, address: u8, command: u8, buffer: &mut [u8]) -> Result<usize, SmBusError<I2C::Error>> {
+        if buffer.len() > BLOCK_MAX {
+            return Err(SmBusError::BlockLength);
+        }
+
+        // count + data + PEC
+        let mut frame = [0u8; BLOCK_MAX + 2];
+        let len = (buffer.len() + 1 + self.pec as usize).max(2);
+        let count = self.i2c.write_read_counted(address, &[command], &mut frame[..len], self.pec as usize)?;
+        if count > buffer.len() {
+            return Err(SmBusError::BlockLength);
+        }
+
+        if self.pec {
+            let crc = crc8(0, &[write_address(address), command, read_address(address)]);
+            if crc8(crc, &frame[..=count]) != frame[count + 1] {
+                return Err(SmBusError::Pec);
+            }
+        }
+
+        buffer[..count].copy_from_slice(&frame[1..=count]);
+        Ok(count)
+    }
+}
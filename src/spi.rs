@@ -0,0 +1,799 @@
+//! Serial Peripheral Interface (SPI0) master.
+//!
+//! The bit rate is derived from PCLKA. Frames of 8 to 16 bits go through the
+//! peripheral's four-frame transmit and receive buffers: each transfer uses
+//! the deepest buffer setting (SPDCR.SPFC) that divides its length evenly, so
+//! the buffer depth only changes between transfers.
+//!
+//! `Spi0Device` shares the bus between devices on the hardware slave select
+//! pins (SSLA0-SSLA3). Each device carries its own SPCMD settings (mode, bit
+//! rate, bit order, frame length and delays), which are loaded when a
+//! transaction switches to it. SSL timing is left to the peripheral: it
+//! asserts SSL the setup delay before the first clock, holds it between
+//! frames (SPCMD.SSLKP), and negates it the hold delay after the last clock
+//! once SSLKP is cleared for the final transfer, keeping it negated for the
+//! next-access delay.
+//!
+//! `Spi0` is behind the `spi0` Cargo feature. `BitOrder` and `SpiError` are
+//! shared with the SCI simple SPI driver.
+
+#[cfg(feature = "spi0")]
+use core::{cell::RefCell, marker::PhantomData};
+
+use embassy_time::Duration;
+#[cfg(feature = "spi0")]
+use embedded_hal::spi::{Operation, Phase, Polarity};
+#[cfg(feature = "spi0")]
+use ra4m2_pac::{spi0::{spcmd::{Cpha, Cpol, Lsbf, Sckden, Slnden, Spnden, Sslkp}, spcr::{Modfen, Mstr, Spe, Spms, Txmd}, spdcr::{Spbyt, Splw}, spsr::{Modf, Ovrf}}, NoBitfieldReg, RegisterValue};
+
+#[cfg(feature = "spi0")]
+use crate::{gpio::{AlternateFunction, Output}, power, sysc::SystemClock, timeout::Deadline};
+
+/// Order in which the bits of a frame are shifted out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SpiError {
+    /// A frame was received before the previous one was read.
+    Overrun,
+    /// Another master drove SSL while this one was transmitting.
+    ModeFault,
+    /// A buffer or transfer-complete flag didn't set within the configured
+    /// timeout, or in slave mode SSLA0 stayed asserted with no frame.
+    Timeout,
+}
+
+impl embedded_hal::spi::Error for SpiError {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        match *self {
+            SpiError::Overrun => embedded_hal::spi::ErrorKind::Overrun,
+            SpiError::ModeFault => embedded_hal::spi::ErrorKind::ModeFault,
+            SpiError::Timeout => embedded_hal::spi::ErrorKind::Other,
+        }
+    }
+}
+
+/// SPI0 master configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiConfig {
+    /// RSPCK frequency in Hz. The fastest rate not above it is used.
+    pub frequency: u32,
+    pub mode: embedded_hal::spi::Mode,
+    pub bit_order: BitOrder,
+    /// Frame length for `u16` transfers, 8 to 16 bits. `u8` transfers always
+    /// use 8-bit frames.
+    pub word_bits: u8,
+    /// How long each wait on the buffers, and for the end of a transfer,
+    /// may take before it is reported as `SpiError::Timeout`. Measured in
+    /// wall-clock time like `I2cConfig`'s.
+    pub timeout: Duration,
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        SpiConfig {
+            frequency: 1_000_000,
+            mode: embedded_hal::spi::MODE_0,
+            bit_order: BitOrder::MsbFirst,
+            word_bits: 16,
+            timeout: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Picks the bit rate register (SPBR) and divider (SPCMD.BRDV) for the
+/// fastest rate not above `frequency`:
+///
+///   B = PCLK / (2 * (n + 1) * 2^N)
+///
+/// Returns `None` if even the slowest rate is too fast.
+#[cfg(feature = "spi0")]
+pub(crate) fn bit_rate_setting(pclk: u32, frequency: u32) -> Option<(u8, u8)> {
+    if frequency == 0 {
+        return None;
+    }
+
+    for brdv in 0..4u8 {
+        let divisor = 2 * (1u64 << brdv) * frequency as u64;
+        let n = (pclk as u64).div_ceil(divisor).max(1);
+        if n <= 256 {
+            return Some(((n - 1) as u8, brdv));
+        }
+    }
+    None
+}
+
+/// SPCMD.SPB value for a frame of `bits` (8 to 16).
+#[cfg(feature = "spi0")]
+pub(crate) fn frame_length_setting(bits: u8) -> u8 {
+    assert!((8..=16).contains(&bits), "SPI frame length must be 8 to 16 bits");
+    // 0b0100..=0b0111 all select 8 bits; 9 to 16 bits are bits - 1
+    bits - 1
+}
+
+/// Frame data types the buffers are accessed with.
+#[cfg(feature = "spi0")]
+pub(crate) trait Word: Copy {
+    /// Value clocked out while only reading.
+    const FILL: Self;
+
+    /// Frame length used for this word type, given the configured length
+    /// for `u16` words.
+    fn frame_bits(word_bits: u8) -> u8;
+
+    fn to_frame(self) -> u32;
+    fn from_frame(frame: u32) -> Self;
+}
+
+#[cfg(feature = "spi0")]
+impl Word for u8 {
+    const FILL: Self = 0xFF;
+
+    fn frame_bits(_word_bits: u8) -> u8 {
+        8
+    }
+
+    fn to_frame(self) -> u32 {
+        self as u32
+    }
+
+    fn from_frame(frame: u32) -> Self {
+        frame as u8
+    }
+}
+
+#[cfg(feature = "spi0")]
+impl Word for u16 {
+    const FILL: Self = 0xFFFF;
+
+    fn frame_bits(word_bits: u8) -> u8 {
+        word_bits
+    }
+
+    fn to_frame(self) -> u32 {
+        self as u32
+    }
+
+    fn from_frame(frame: u32) -> Self {
+        frame as u16
+    }
+}
+
+/// Largest buffer depth (1 to 4 frames) that divides `frames` evenly.
+#[cfg(feature = "spi0")]
+pub(crate) fn buffer_depth(frames: usize) -> u8 {
+    (1..=4u8).rev().find(|depth| frames % *depth as usize == 0).unwrap_or(1)
+}
+
+#[cfg(feature = "spi0")]
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Frames clocked by a device operation.
+#[cfg(feature = "spi0")]
+fn frame_count<W>(operation: &Operation<'_, W>) -> usize {
+    match operation {
+        Operation::Read(words) => words.len(),
+        Operation::Write(words) => words.len(),
+        Operation::Transfer(read, write) => read.len().max(write.len()),
+        Operation::TransferInPlace(words) => words.len(),
+        Operation::DelayNs(_) => 0,
+    }
+}
+
+/// ICU event number for SPI0 receive buffer full (SPRI).
+#[cfg(feature = "spi0")]
+pub(crate) const SPRI_EVENT: u16 = 0x0D0;
+/// ICU event number for SPI0 transmit buffer empty (SPTI).
+#[cfg(feature = "spi0")]
+pub(crate) const SPTI_EVENT: u16 = 0x0D1;
+/// ICU event number for SPI0 errors (SPEI).
+#[cfg(feature = "spi0")]
+pub(crate) const SPEI_EVENT: u16 = 0x0D3;
+/// Address of SPI0's data register (SPDR), for DTC transfers.
+#[cfg(feature = "spi0")]
+pub(crate) const SPDR_ADDRESS: u32 = 0x4011_A004;
+
+/// IELSR slots the application assigned to the SPI0 events, for the
+/// interrupt-driven drivers.
+#[cfg(feature = "spi0")]
+#[derive(Debug, Clone, Copy)]
+pub struct SpiInterrupts {
+    pub spri: ra4m2_pac::Interrupt,
+    pub spti: ra4m2_pac::Interrupt,
+    pub spei: ra4m2_pac::Interrupt,
+}
+
+/// Marks a pin that can carry RSPCKA.
+pub trait RspckPin {}
+/// Marks a pin that can carry MOSIA.
+pub trait MosiPin {}
+/// Marks a pin that can carry MISOA.
+pub trait MisoPin {}
+/// Marks a pin that can carry SSLAn, with `SSL` = n.
+pub trait SslPin {
+    const SSL: u8;
+}
+/// Marks a pin that can carry SSLA0, the only slave select input in slave
+/// mode.
+pub trait Ssla0Pin: SslPin {
+    /// Reads the pin level, for the slave driver.
+    #[doc(hidden)]
+    fn level() -> bool;
+}
+
+macro_rules! impl_spi_pin {
+    ($feature:literal, $port:ident, $n:literal, $pin_trait:ident) => {
+        #[cfg(all(feature = $feature, feature = "spi0"))]
+        impl $pin_trait for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {}
+    };
+}
+
+// Pin assignments from the "Peripheral Select Settings" tables of the
+// RA4M2 User's Manual. Mux with `PinFunction::SPI`.
+impl_spi_pin!("port1", port1, 0, MisoPin); // MISOA_A
+impl_spi_pin!("port1", port1, 1, MosiPin); // MOSIA_A
+impl_spi_pin!("port1", port1, 2, RspckPin); // RSPCKA_A
+impl_spi_pin!("port1", port1, 10, MisoPin); // MISOA_B
+impl_spi_pin!("port1", port1, 9, MosiPin); // MOSIA_B
+impl_spi_pin!("port1", port1, 11, RspckPin); // RSPCKA_B
+
+macro_rules! impl_ssl_pin {
+    ($feature:literal, $port:ident, $n:literal, $ssl:literal) => {
+        #[cfg(all(feature = $feature, feature = "spi0"))]
+        impl SslPin for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {
+            const SSL: u8 = $ssl;
+        }
+    };
+}
+
+macro_rules! impl_ssla0_pin {
+    ($feature:literal, $port:ident, $n:literal) => {
+        impl_ssl_pin!($feature, $port, $n, 0);
+
+        #[cfg(all(feature = $feature, feature = "spi0"))]
+        impl Ssla0Pin for crate::gpio::$port::Pin<Output<AlternateFunction>, $n> {
+            fn level() -> bool {
+                crate::pfsel::$port::get_pin_value($n)
+            }
+        }
+    };
+}
+
+impl_ssla0_pin!("port1", port1, 3); // SSLA0_A
+impl_ssl_pin!("port1", port1, 4, 1); // SSLA1_A
+impl_ssl_pin!("port1", port1, 5, 2); // SSLA2_A
+impl_ssl_pin!("port1", port1, 6, 3); // SSLA3_A
+impl_ssla0_pin!("port1", port1, 12); // SSLA0_B
+impl_ssl_pin!("port1", port1, 13, 1); // SSLA1_B
+impl_ssl_pin!("port1", port1, 14, 2); // SSLA2_B
+impl_ssl_pin!("port1", port1, 15, 3); // SSLA3_B
+
+/// SPI0 master driver. Chip select is left to the caller (a GPIO, e.g.
+/// through `embedded_hal_bus::spi::ExclusiveDevice`). Pins are borrowed for
+/// `'d` the same way as for `i2c::I2c`.
+#[cfg(feature = "spi0")]
+pub struct Spi0<'d> {
+    spi: ra4m2_pac::Spi0,
+    config: SpiConfig,
+    /// Frame length and buffer depth currently programmed.
+    frame: (u8, u8),
+    /// Frame length for `u16` words: the bus's, or the current device's.
+    word_bits: u8,
+    /// Buffer depth fixed for the whole of a device transaction.
+    locked_depth: Option<u8>,
+    /// SPBR and SPCMD0 for plain bus use, restored when the bus is used
+    /// after device transactions.
+    bus_command: (u8, u16),
+    /// Device whose settings are programmed (SPI mode), or `None` for plain
+    /// bus use (clock synchronous mode).
+    device: Option<DeviceCommand>,
+    /// Clear SSLKP before the last transfer of the current `run`, so the
+    /// peripheral negates SSL after it.
+    release_ssl: bool,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+#[cfg(feature = "spi0")]
+impl<'d> Spi0<'d> {
+    /// Creates the driver. Panics if `config.frequency` can't be reached from
+    /// PCLKA or `config.word_bits` is out of range.
+    pub fn new<SCK: RspckPin, MOSI: MosiPin, MISO: MisoPin>(
+        spi: ra4m2_pac::Spi0,
+        _sck: &'d mut SCK,
+        _mosi: &'d mut MOSI,
+        _miso: &'d mut MISO,
+        system_clock: &SystemClock,
+        config: SpiConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            power::enable_spi0(cs);
+        });
+
+        let (spbr, brdv) = bit_rate_setting(system_clock.get_pclka_freq(), config.frequency)
+            .expect("SPI frequency out of range for PCLKA");
+        frame_length_setting(config.word_bits);
+
+        let cpha = match config.mode.phase {
+            Phase::CaptureOnFirstTransition => Cpha::_0,
+            Phase::CaptureOnSecondTransition => Cpha::_1,
+        };
+        let cpol = match config.mode.polarity {
+            Polarity::IdleLow => Cpol::_0,
+            Polarity::IdleHigh => Cpol::_1,
+        };
+        let lsbf = match config.bit_order {
+            BitOrder::MsbFirst => Lsbf::_0,
+            BitOrder::LsbFirst => Lsbf::_1,
+        };
+
+        // See "SPI Initialization" flowchart in the RA4M2 manual
+        unsafe {
+            spi.spcr().modify(|w| w.set_raw(0));
+
+            spi.spbr().modify(|w| w.set(spbr));
+            spi.spdcr().modify(|w| w.spfc().set(0.into()).splw().set(Splw::_1).spbyt().set(Spbyt::_0));
+            // Only SPCMD0 is used
+            spi.spscr().modify(|w| w.set_raw(0));
+            spi.spcmd().get(0).modify(|w| {
+                w.cpha().set(cpha).cpol().set(cpol).brdv().set(brdv.into()).lsbf().set(lsbf)
+                    .spb().set(frame_length_setting(8).into())
+            });
+
+            // Clock synchronous (3-wire) operation: no SSL, full duplex
+            spi.spcr().modify(|w| {
+                w.spms().set(Spms::_1).txmd().set(Txmd::_0).modfen().set(Modfen::_0).mstr().set(Mstr::_1)
+            });
+            spi.spcr().modify(|w| w.spe().set(Spe::_1));
+        }
+
+        let bus_command = unsafe { (spbr, spi.spcmd().get(0).read().get_raw()) };
+
+        Spi0 {
+            spi,
+            config,
+            frame: (8, 1),
+            word_bits: config.word_bits,
+            locked_depth: None,
+            bus_command,
+            device: None,
+            release_ssl: false,
+            _pins: PhantomData,
+        }
+    }
+
+    /// Releases the peripheral.
+    pub fn free(self) -> ra4m2_pac::Spi0 {
+        unsafe {
+            self.spi.spcr().modify(|w| w.spe().set(Spe::_0));
+        }
+        self.spi
+    }
+
+    /// Register block, for the async driver.
+    pub(crate) fn regs(&self) -> &ra4m2_pac::Spi0 {
+        &self.spi
+    }
+
+    /// Programs the frame length and buffer depth if they differ from the
+    /// current ones. SPE is cleared around the change, so only call between
+    /// transfers.
+    pub(crate) fn set_frame(&mut self, bits: u8, depth: u8) {
+        if self.frame == (bits, depth) {
+            return;
+        }
+        unsafe {
+            self.spi.spcr().modify(|w| w.spe().set(Spe::_0));
+        }
+        self.program_frame(bits, depth);
+        unsafe {
+            self.spi.spcr().modify(|w| w.spe().set(Spe::_1));
+        }
+    }
+
+    /// Writes the frame length and buffer depth. SPE must be clear.
+    fn program_frame(&mut self, bits: u8, depth: u8) {
+        unsafe {
+            self.spi.spdcr().modify(|w| w.spfc().set((depth - 1).into()));
+            self.spi.spcmd().get(0).modify(|w| w.spb().set(frame_length_setting(bits).into()));
+        }
+        self.frame = (bits, depth);
+    }
+
+    /// Starts a device transaction. The first transaction after using
+    /// another device or the plain bus switches to SPI (4-wire) operation
+    /// with the device's command; later ones only set SSLKP again, so SPE
+    /// isn't toggled unless the frame settings change. SSL asserts with the
+    /// first frame and stays asserted until the transfer after
+    /// `release_ssl_after_next_run`.
+    fn begin_device(&mut self, device: &DeviceCommand, bits: u8, depth: u8) {
+        if self.device == Some(*device) {
+            self.set_frame(bits, depth);
+            unsafe {
+                // SPCMD0 may be written while SPE is set as long as the
+                // peripheral is idle
+                self.spi.spcmd().get(0).modify(|w| w.sslkp().set(Sslkp::_1));
+            }
+        } else {
+            unsafe {
+                self.spi.spcr().modify(|w| w.spe().set(Spe::_0));
+                self.spi.spcr().modify(|w| w.spms().set(Spms::_0));
+                self.spi.spbr().modify(|w| w.set(device.spbr));
+                self.spi.spcmd().get(0).modify(|w| {
+                    w.cpha().set(device.cpha).cpol().set(device.cpol).brdv().set(device.brdv.into())
+                        .lsbf().set(device.lsbf).ssla().set(device.ssl.into()).sslkp().set(Sslkp::_1)
+                        .sckden().set(Sckden::_1).slnden().set(Slnden::_1).spnden().set(Spnden::_1)
+                });
+                // Delay registers hold the cycle count minus one
+                self.spi.spckd().modify(|w| w.sckdl().set((device.setup_delay - 1).into()));
+                self.spi.sslnd().modify(|w| w.slndl().set((device.hold_delay - 1).into()));
+                self.spi.spnd().modify(|w| w.spndl().set((device.next_access_delay - 1).into()));
+            }
+            self.program_frame(bits, depth);
+            self.device = Some(*device);
+            unsafe {
+                self.spi.spcr().modify(|w| w.spe().set(Spe::_1));
+            }
+        }
+        self.word_bits = device.word_bits;
+        self.locked_depth = Some(depth);
+    }
+
+    /// Makes the next `run` clear SSLKP before its last transfer, ending the
+    /// device transaction with it.
+    fn release_ssl_after_next_run(&mut self) {
+        self.release_ssl = true;
+    }
+
+    /// Clears SSLKP once the previous transfer is done, so SSL is negated
+    /// after the next one.
+    fn clear_sslkp(&mut self) -> Result<(), SpiError> {
+        self.release_ssl = false;
+        self.wait_for(Self::transfer_complete)?;
+        unsafe {
+            self.spi.spcmd().get(0).modify(|w| w.sslkp().set(Sslkp::_0));
+        }
+        Ok(())
+    }
+
+    /// Ends a device transaction. If no transfer released SSL (the
+    /// transaction ended on a delay or had no frames) or the last transfer
+    /// didn't complete in time, it is negated by clearing SPE, without the
+    /// hold and next-access delays.
+    fn end_device(&mut self, ssl_released: bool) -> Result<(), SpiError> {
+        let result = self.wait_for(Self::transfer_complete);
+
+        if !ssl_released || result.is_err() {
+            unsafe {
+                self.spi.spcr().modify(|w| w.spe().set(Spe::_0));
+                self.spi.spcmd().get(0).modify(|w| w.sslkp().set(Sslkp::_0));
+                self.spi.spcr().modify(|w| w.spe().set(Spe::_1));
+            }
+        }
+        self.release_ssl = false;
+        self.word_bits = self.config.word_bits;
+        self.locked_depth = None;
+        result
+    }
+
+    /// Returns to the bus settings after device transactions.
+    pub(crate) fn use_bus(&mut self) {
+        if self.device.take().is_none() {
+            return;
+        }
+        unsafe {
+            self.spi.spcr().modify(|w| w.spe().set(Spe::_0));
+            self.spi.spcr().modify(|w| w.spms().set(Spms::_1));
+            self.spi.spbr().modify(|w| w.set(self.bus_command.0));
+            self.spi.spcmd().get(0).modify(|w| w.set_raw(self.bus_command.1));
+        }
+        self.program_frame(8, 1);
+        unsafe {
+            self.spi.spcr().modify(|w| w.spe().set(Spe::_1));
+        }
+    }
+
+    /// Busy-waits until `condition` holds, failing with `SpiError::Timeout`
+    /// once the configured timeout has elapsed.
+    fn wait_for(&self, condition: impl Fn(&Self) -> bool) -> Result<(), SpiError> {
+        let deadline = Deadline::after(self.config.timeout);
+        while !condition(self) {
+            if deadline.expired() {
+                return Err(SpiError::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn error_pending(&self) -> bool {
+        unsafe {
+            let spsr = self.spi.spsr().read();
+            spsr.ovrf().get().0 == 1 || spsr.modf().get().0 == 1
+        }
+    }
+
+    fn check_errors(&mut self) -> Result<(), SpiError> {
+        unsafe {
+            let spsr = self.spi.spsr().read();
+            if spsr.ovrf().get().0 == 1 {
+                self.spi.spsr().modify(|w| w.ovrf().set(Ovrf::_0));
+                return Err(SpiError::Overrun);
+            }
+            if spsr.modf().get().0 == 1 {
+                self.spi.spsr().modify(|w| w.modf().set(Modf::_0));
+                return Err(SpiError::ModeFault);
+            }
+        }
+        Ok(())
+    }
+
+    fn transmit_buffer_empty(&self) -> bool {
+        unsafe {
+            self.spi.spsr().read().sptef().get().0 == 1
+        }
+    }
+
+    fn receive_buffer_full(&self) -> bool {
+        unsafe {
+            self.spi.spsr().read().sprf().get().0 == 1
+        }
+    }
+
+    pub(crate) fn transfer_complete(&self) -> bool {
+        unsafe {
+            self.spi.spsr().read().idlnf().get().0 == 0
+        }
+    }
+
+    /// Clocks out `max(read.len(), write.len())` frames, filling
+    /// `write` out with `W::FILL` and dropping received frames past
+    /// `read.len()`.
+    fn run<W: Word>(&mut self, read: &mut [W], write: &[W]) -> Result<(), SpiError> {
+        let frames = read.len().max(write.len());
+        if frames == 0 {
+            return Ok(());
+        }
+
+        if self.locked_depth.is_none() {
+            self.use_bus();
+        }
+        let depth = self.locked_depth.unwrap_or_else(|| buffer_depth(frames));
+        self.set_frame(W::frame_bits(self.word_bits), depth);
+
+        for chunk in (0..frames).step_by(depth as usize) {
+            if self.release_ssl && chunk + depth as usize >= frames {
+                self.clear_sslkp()?;
+            }
+            self.wait_for(Self::transmit_buffer_empty)?;
+            for i in chunk..chunk + depth as usize {
+                let frame = write.get(i).copied().unwrap_or(W::FILL).to_frame();
+                unsafe {
+                    // A plain write; `modify` would read SPDR and pop the
+                    // receive buffer
+                    self.spi.spdr().init(|w| w.set(frame));
+                }
+            }
+
+            self.wait_for(|s| s.receive_buffer_full() || s.error_pending())?;
+            self.check_errors()?;
+            for i in chunk..chunk + depth as usize {
+                let frame = unsafe { self.spi.spdr().read().get() };
+                if let Some(word) = read.get_mut(i) {
+                    *word = W::from_frame(frame);
+                }
+            }
+        }
+        self.check_errors()
+    }
+
+    fn run_in_place<W: Word>(&mut self, words: &mut [W]) -> Result<(), SpiError> {
+        let depth = self.locked_depth.unwrap_or_else(|| buffer_depth(words.len())) as usize;
+        // Only the last chunk may release SSL
+        let release_ssl = core::mem::take(&mut self.release_ssl);
+        let chunks = words.len().div_ceil(depth);
+        // Each chunk is written out before it is read back, so going chunk
+        // by chunk through a copy keeps the single buffer safe
+        for (i, chunk) in words.chunks_mut(depth).enumerate() {
+            self.release_ssl = release_ssl && i + 1 == chunks;
+            let mut out = [W::FILL; 4];
+            out[..chunk.len()].copy_from_slice(chunk);
+            self.run(chunk, &out[..chunk.len()])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "spi0")]
+impl<'d> embedded_hal::spi::ErrorType for Spi0<'d> {
+    type Error = SpiError;
+}
+
+macro_rules! impl_spi_bus {
+    ($word:ty) => {
+        #[cfg(feature = "spi0")]
+        impl<'d> embedded_hal::spi::SpiBus<$word> for Spi0<'d> {
+            /// Clocks out all ones while reading.
+            fn read(&mut self, words: &mut [$word]) -> Result<(), Self::Error> {
+                self.run(words, &[])
+            }
+
+            fn write(&mut self, words: &[$word]) -> Result<(), Self::Error> {
+                self.run(&mut [], words)
+            }
+
+            fn transfer(&mut self, read: &mut [$word], write: &[$word]) -> Result<(), Self::Error> {
+                self.run(read, write)
+            }
+
+            fn transfer_in_place(&mut self, words: &mut [$word]) -> Result<(), Self::Error> {
+                self.run_in_place(words)
+            }
+
+            fn flush(&mut self) -> Result<(), Self::Error> {
+                self.wait_for(Self::transfer_complete)
+            }
+        }
+    };
+}
+
+impl_spi_bus!(u8);
+impl_spi_bus!(u16);
+
+/// Per-device settings of `Spi0Device`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiDeviceConfig {
+    /// RSPCK frequency in Hz. The fastest rate not above it is used.
+    pub frequency: u32,
+    pub mode: embedded_hal::spi::Mode,
+    pub bit_order: BitOrder,
+    /// Frame length for `u16` transfers, 8 to 16 bits.
+    pub word_bits: u8,
+    /// RSPCK cycles from SSL assertion to the first clock edge, 1 to 8.
+    pub setup_delay: u8,
+    /// RSPCK cycles from the last clock edge to SSL negation, 1 to 8.
+    pub hold_delay: u8,
+    /// RSPCK cycles SSL stays negated before the next transaction, 1 to 8.
+    pub next_access_delay: u8,
+}
+
+impl Default for SpiDeviceConfig {
+    fn default() -> Self {
+        SpiDeviceConfig {
+            frequency: 1_000_000,
+            mode: embedded_hal::spi::MODE_0,
+            bit_order: BitOrder::MsbFirst,
+            word_bits: 16,
+            setup_delay: 1,
+            hold_delay: 1,
+            next_access_delay: 1,
+        }
+    }
+}
+
+/// A device's SPCMD settings, precomputed.
+#[cfg(feature = "spi0")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct DeviceCommand {
+    spbr: u8,
+    brdv: u8,
+    cpha: Cpha,
+    cpol: Cpol,
+    lsbf: Lsbf,
+    ssl: u8,
+    word_bits: u8,
+    setup_delay: u8,
+    hold_delay: u8,
+    next_access_delay: u8,
+}
+
+/// An SPI device on a hardware slave select pin, sharing an `Spi0` through a
+/// `RefCell`.
+///
+/// SSL is held asserted for the whole transaction (SPCMD.SSLKP), so
+/// operations run back to back without releasing the device, and is negated
+/// by the peripheral after the transaction's last frame. The setup, hold and
+/// next-access delays are all timed by the peripheral (SPCKD, SSLND, SPND).
+#[cfg(feature = "spi0")]
+pub struct Spi0Device<'a, 'd> {
+    bus: &'a RefCell<Spi0<'d>>,
+    command: DeviceCommand,
+    _ssl: PhantomData<&'a mut ()>,
+}
+
+#[cfg(feature = "spi0")]
+impl<'a, 'd> Spi0Device<'a, 'd> {
+    /// Creates a device selected by `ssl`. Panics if `config.frequency`
+    /// can't be reached from PCLKA, or a delay or the frame length is out of
+    /// range.
+    pub fn new<SSL: SslPin>(
+        bus: &'a RefCell<Spi0<'d>>,
+        _ssl: &'a mut SSL,
+        system_clock: &SystemClock,
+        config: SpiDeviceConfig,
+    ) -> Self {
+        let pclk = system_clock.get_pclka_freq();
+        let (spbr, brdv) = bit_rate_setting(pclk, config.frequency).expect("SPI frequency out of range for PCLKA");
+        frame_length_setting(config.word_bits);
+        for delay in [config.setup_delay, config.hold_delay, config.next_access_delay] {
+            assert!((1..=8).contains(&delay), "SPI delays must be 1 to 8 RSPCK cycles");
+        }
+
+        let command = DeviceCommand {
+            spbr,
+            brdv,
+            cpha: match config.mode.phase {
+                Phase::CaptureOnFirstTransition => Cpha::_0,
+                Phase::CaptureOnSecondTransition => Cpha::_1,
+            },
+            cpol: match config.mode.polarity {
+                Polarity::IdleLow => Cpol::_0,
+                Polarity::IdleHigh => Cpol::_1,
+            },
+            lsbf: match config.bit_order {
+                BitOrder::MsbFirst => Lsbf::_0,
+                BitOrder::LsbFirst => Lsbf::_1,
+            },
+            ssl: SSL::SSL,
+            word_bits: config.word_bits,
+            setup_delay: config.setup_delay,
+            hold_delay: config.hold_delay,
+            next_access_delay: config.next_access_delay,
+        };
+
+        Spi0Device { bus, command, _ssl: PhantomData }
+    }
+}
+
+#[cfg(feature = "spi0")]
+impl<'a, 'd> embedded_hal::spi::ErrorType for Spi0Device<'a, 'd> {
+    type Error = SpiError;
+}
+
+macro_rules! impl_spi_device {
+    ($word:ty) => {
+        #[cfg(feature = "spi0")]
+        impl<'a, 'd> embedded_hal::spi::SpiDevice<$word> for Spi0Device<'a, 'd> {
+            fn transaction(&mut self, operations: &mut [Operation<'_, $word>]) -> Result<(), Self::Error> {
+                // The buffer depth can't change while SSL is held, so pick
+                // one that divides every operation
+                let frames = operations.iter().fold(0, |frames, operation| gcd(frames, frame_count(operation)));
+                let depth = buffer_depth(frames.max(1));
+
+                // SSL is released by the peripheral after the last frame,
+                // provided the transaction ends with a transfer
+                let ssl_released = matches!(operations.last(), Some(operation) if frame_count(operation) > 0);
+                let last = operations.len().saturating_sub(1);
+
+                let mut bus = self.bus.borrow_mut();
+                let bits = <$word as Word>::frame_bits(self.command.word_bits);
+                bus.begin_device(&self.command, bits, depth);
+                let result = operations.iter_mut().enumerate().try_for_each(|(i, operation)| {
+                    if ssl_released && i == last {
+                        bus.release_ssl_after_next_run();
+                    }
+                    match operation {
+                        Operation::Read(words) => bus.run(words, &[]),
+                        Operation::Write(words) => bus.run(&mut [], words),
+                        Operation::Transfer(read, write) => bus.run(read, write),
+                        Operation::TransferInPlace(words) => bus.run_in_place(words),
+                        Operation::DelayNs(ns) => {
+                            let delay = Deadline::after(Duration::from_micros((*ns as u64).div_ceil(1_000)));
+                            while !delay.expired() {}
+                            Ok(())
+                        }
+                    }
+                });
+                let ended = bus.end_device(ssl_released && result.is_ok());
+                result.and(ended)
+            }
+        }
+    };
+}
+
+impl_spi_device!(u8);
+impl_spi_device!(u16);
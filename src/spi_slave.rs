@@ -0,0 +1,294 @@
+//! SPI0 slave mode, for running as a co-processor behind a host SPI master.
+//!
+//! The host selects the RA4M2 on SSLA0. Frames are moved between the
+//! peripheral and the caller's buffers by the SPRI / SPTI interrupt handlers,
+//! and `transfer` completes once the host negates SSLA0. A host that negates
+//! SSLA0 in the middle of a frame raises a mode fault.
+//!
+//! No slave mode event marks the negation itself (the idle flag follows SPE,
+//! not SSL), so once selected the transfer future re-polls the SSLA0 pin,
+//! keeping the executor busy. The re-poll is bounded: if the host neither
+//! clocks another frame nor negates SSLA0 within `ssl_timeout`, `transfer`
+//! gives up with `SpiError::Timeout`.
+//!
+//! The application routes the three SPI events to IELSR slots of its choice
+//! and forwards those interrupts to the `on_*` handlers, as for
+//! `async_uart::AsyncUart`.
+
+use core::{cell::RefCell, future::poll_fn, marker::PhantomData, task::Poll};
+
+use cortex_m::interrupt::Mutex;
+use embassy_time::Duration;
+use embedded_hal::spi::{Phase, Polarity};
+use ra4m2_pac::{spi0::{spcmd::{Cpha, Cpol, Lsbf}, spcr::{Modfen, Mstr, Spe, Speie, Spms, Sprie, Sptie, Txmd}, spdcr::{Spbyt, Splw}, spsr::{Modf, Ovrf}}, Interrupt, NoBitfieldReg, RegisterValue};
+
+use crate::{icu, power, spi::{frame_length_setting, BitOrder, MisoPin, MosiPin, RspckPin, SpiError, SpiInterrupts, Ssla0Pin, SPEI_EVENT, SPRI_EVENT, SPTI_EVENT}, timeout::Deadline, waker::WakerSlot};
+
+/// SPI0 slave configuration. The bit rate is set by the host; RSPCK may run
+/// at up to PCLKA / 4.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiSlaveConfig {
+    /// Must match the host's. With phase 0 the host has to negate SSLA0
+    /// between frames.
+    pub mode: embedded_hal::spi::Mode,
+    pub bit_order: BitOrder,
+    /// How long SSLA0 may stay asserted after the last frame, or between
+    /// frames, while the transfer re-polls the pin for its negation.
+    pub ssl_timeout: Duration,
+}
+
+impl Default for SpiSlaveConfig {
+    fn default() -> Self {
+        SpiSlaveConfig {
+            mode: embedded_hal::spi::MODE_1,
+            bit_order: BitOrder::MsbFirst,
+            ssl_timeout: Duration::from_millis(1),
+        }
+    }
+}
+
+/// Buffers of the transfer in progress, shared with the interrupt handlers.
+struct Transfer {
+    rx: *mut u8,
+    rx_len: usize,
+    tx: *const u8,
+    tx_len: usize,
+    /// Frames received.
+    received: usize,
+    /// Frames loaded for transmission.
+    loaded: usize,
+    error: Option<SpiError>,
+}
+
+// The pointers are only dereferenced by the handlers while `transfer` keeps
+// the buffers borrowed.
+unsafe impl Send for Transfer {}
+
+static TRANSFER: Mutex<RefCell<Option<Transfer>>> = Mutex::new(RefCell::new(None));
+static WAKER: WakerSlot = WakerSlot::new();
+
+/// Value shifted out once the transmit buffer is used up.
+const FILL: u8 = 0xFF;
+
+/// SPI0 slave driver. Pins are borrowed for `'d` the same way as for
+/// `i2c::I2c`.
+pub struct Spi0Slave<'d> {
+    spi: ra4m2_pac::Spi0,
+    /// Reads the SSLA0 pin level.
+    ssl_level: fn() -> bool,
+    ssl_timeout: Duration,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d> Spi0Slave<'d> {
+    /// Sets up slave mode.
+    pub fn new<SCK: RspckPin, MOSI: MosiPin, MISO: MisoPin, SSL: Ssla0Pin>(
+        spi: ra4m2_pac::Spi0,
+        _sck: &'d mut SCK,
+        _mosi: &'d mut MOSI,
+        _miso: &'d mut MISO,
+        _ssl: &'d mut SSL,
+        config: SpiSlaveConfig,
+        interrupts: SpiInterrupts,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            power::enable_spi0(cs);
+        });
+
+        let cpha = match config.mode.phase {
+            Phase::CaptureOnFirstTransition => Cpha::_0,
+            Phase::CaptureOnSecondTransition => Cpha::_1,
+        };
+        let cpol = match config.mode.polarity {
+            Polarity::IdleLow => Cpol::_0,
+            Polarity::IdleHigh => Cpol::_1,
+        };
+        let lsbf = match config.bit_order {
+            BitOrder::MsbFirst => Lsbf::_0,
+            BitOrder::LsbFirst => Lsbf::_1,
+        };
+
+        unsafe {
+            spi.spcr().modify(|w| w.set_raw(0));
+            spi.spdcr().modify(|w| w.spfc().set(0.into()).splw().set(Splw::_1).spbyt().set(Spbyt::_0));
+            spi.spscr().modify(|w| w.set_raw(0));
+            spi.spcmd().get(0).modify(|w| {
+                w.cpha().set(cpha).cpol().set(cpol).lsbf().set(lsbf).spb().set(frame_length_setting(8).into())
+            });
+            // SPI (4-wire) slave with mode fault detection on SSLA0
+            spi.spcr().modify(|w| {
+                w.spms().set(Spms::_0).txmd().set(Txmd::_0).modfen().set(Modfen::_1).mstr().set(Mstr::_0)
+            });
+        }
+
+        icu::register_interrupt(interrupts.spri, SPRI_EVENT);
+        icu::register_interrupt(interrupts.spti, SPTI_EVENT);
+        icu::register_interrupt(interrupts.spei, SPEI_EVENT);
+
+        Spi0Slave {
+            spi,
+            ssl_level: SSL::level,
+            ssl_timeout: config.ssl_timeout,
+            _pins: PhantomData,
+        }
+    }
+
+    /// Releases the peripheral.
+    pub fn free(self) -> ra4m2_pac::Spi0 {
+        unsafe {
+            self.spi.spcr().modify(|w| w.set_raw(0));
+        }
+        self.spi
+    }
+
+    /// SPRI handler: stores a received frame.
+    pub fn on_spri(interrupt: Interrupt) {
+        cortex_m::interrupt::free(|cs| {
+            let frame = unsafe { ra4m2_pac::SPI0.spdr().read().get() } as u8;
+            if let Some(transfer) = TRANSFER.borrow(cs).borrow_mut().as_mut() {
+                if transfer.received < transfer.rx_len {
+                    unsafe {
+                        transfer.rx.add(transfer.received).write(frame);
+                    }
+                }
+                transfer.received += 1;
+            }
+        });
+        icu::clear_interrupt(interrupt);
+        WAKER.wake();
+    }
+
+    /// SPTI handler: loads the next frame to send.
+    pub fn on_spti(interrupt: Interrupt) {
+        cortex_m::interrupt::free(|cs| {
+            let frame = match TRANSFER.borrow(cs).borrow_mut().as_mut() {
+                Some(transfer) => {
+                    let frame = if transfer.loaded < transfer.tx_len {
+                        unsafe { transfer.tx.add(transfer.loaded).read() }
+                    } else {
+                        FILL
+                    };
+                    transfer.loaded += 1;
+                    frame
+                }
+                None => FILL,
+            };
+            unsafe {
+                // A plain write; `modify` would read SPDR and pop the receive
+                // buffer
+                ra4m2_pac::SPI0.spdr().init(|w| w.set(frame as u32));
+            }
+        });
+        icu::clear_interrupt(interrupt);
+    }
+
+    /// SPEI handler: records an overrun or mode fault. A mode fault also
+    /// clears SPE.
+    pub fn on_spei(interrupt: Interrupt) {
+        cortex_m::interrupt::free(|cs| unsafe {
+            let spi = ra4m2_pac::SPI0;
+            let spsr = spi.spsr().read();
+            let error = if spsr.modf().get().0 == 1 {
+                Some(SpiError::ModeFault)
+            } else if spsr.ovrf().get().0 == 1 {
+                Some(SpiError::Overrun)
+            } else {
+                None
+            };
+            spi.spsr().modify(|w| w.ovrf().set(Ovrf::_0).modf().set(Modf::_0));
+            if let Some(transfer) = TRANSFER.borrow(cs).borrow_mut().as_mut() {
+                transfer.error = transfer.error.or(error);
+            }
+        });
+        icu::clear_interrupt(interrupt);
+        WAKER.wake();
+    }
+
+    fn stop(&mut self) {
+        unsafe {
+            self.spi.spcr().modify(|w| {
+                w.spe().set(Spe::_0).sprie().set(Sprie::_0).sptie().set(Sptie::_0).speie().set(Speie::_0)
+            });
+        }
+        cortex_m::interrupt::free(|cs| {
+            TRANSFER.borrow(cs).replace(None);
+        });
+    }
+
+    /// Serves one host transaction: sends `write` (then 0xFF), receives into
+    /// `read` (dropping frames past its end), and returns the number of
+    /// frames the host clocked once it negates SSLA0. Returns
+    /// `SpiError::Timeout` if SSLA0 stays asserted for `ssl_timeout` with no
+    /// frame.
+    pub async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<usize, SpiError> {
+        // Stops the handlers from touching the buffers even if this future
+        // is dropped early
+        struct Guard<'a, 'd>(&'a mut Spi0Slave<'d>);
+        impl Drop for Guard<'_, '_> {
+            fn drop(&mut self) {
+                self.0.stop();
+            }
+        }
+
+        self.stop();
+        cortex_m::interrupt::free(|cs| {
+            TRANSFER.borrow(cs).replace(Some(Transfer {
+                rx: read.as_mut_ptr(),
+                rx_len: read.len(),
+                tx: write.as_ptr(),
+                tx_len: write.len(),
+                received: 0,
+                loaded: 0,
+                error: None,
+            }));
+        });
+
+        let guard = Guard(self);
+        unsafe {
+            let spi = &guard.0.spi;
+            spi.spsr().modify(|w| w.ovrf().set(Ovrf::_0).modf().set(Modf::_0));
+            // SPTI fires as soon as SPE is set, loading the first frame
+            spi.spcr().modify(|w| w.sprie().set(Sprie::_1).sptie().set(Sptie::_1).speie().set(Speie::_1));
+            spi.spcr().modify(|w| w.spe().set(Spe::_1));
+        }
+
+        let ssl_level = guard.0.ssl_level;
+        let ssl_timeout = guard.0.ssl_timeout;
+        let mut selected = false;
+        // Restarted by every frame, while the pin is re-polled
+        let mut last_frame = (0, Deadline::after(ssl_timeout));
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            let (received, error) = cortex_m::interrupt::free(|cs| {
+                TRANSFER
+                    .borrow(cs)
+                    .borrow()
+                    .as_ref()
+                    .map_or((0, None), |transfer| (transfer.received, transfer.error))
+            });
+            if let Some(error) = error {
+                return Poll::Ready(Err(error));
+            }
+
+            // SSLA0 is active low
+            let active = !ssl_level();
+            if !selected && (active || received > 0) {
+                selected = true;
+                last_frame = (received, Deadline::after(ssl_timeout));
+            }
+            if selected && !active {
+                return Poll::Ready(Ok(received));
+            }
+            if selected {
+                if received != last_frame.0 {
+                    last_frame = (received, Deadline::after(ssl_timeout));
+                } else if last_frame.1.expired() {
+                    return Poll::Ready(Err(SpiError::Timeout));
+                }
+                cx.waker().wake_by_ref();
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
@@ -1,6 +1,18 @@
 /// This module is for the clock generation circuit (CGC) on the RA4M2 MCU.
 
-use ra4m2_pac::{sysc::{sckdivcr::{Ick, Pckb, Pckd, Rsv}, sckscr::Cksel}, RegisterValue};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use ra4m2_pac::{sysc::{sbycr::Ssby, sckdivcr::{Ick, Pckb, Pckd, Rsv}, sckscr::Cksel}, RegisterValue};
+
+/// Cached ICLK frequency in Hz, refreshed whenever `SystemClock` changes the
+/// clock source or ICLK divider. Lets drivers convert durations into core
+/// cycles without holding a `SystemClock`. The chip resets to MOCO (8 MHz).
+static ICLK_HZ: AtomicU32 = AtomicU32::new(8_000_000);
+
+/// Returns the last known ICLK (core clock) frequency in Hz.
+pub fn iclk_hz() -> u32 {
+    ICLK_HZ.load(Ordering::Relaxed)
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -118,6 +130,26 @@ impl Into<Pckd> for ClockDividers {
 
 
 
+/// HOCO frequency, selected by the OFS1.HOCOFRQ0 option setting that is
+/// programmed with the flash image. It can't be read back from the SYSC
+/// registers, so it has to be given in `SystemClockConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HocoFrequency {
+    Mhz16,
+    Mhz18,
+    Mhz20,
+}
+
+impl HocoFrequency {
+    pub fn hz(self) -> u32 {
+        match self {
+            HocoFrequency::Mhz16 => 16_000_000,
+            HocoFrequency::Mhz18 => 18_000_000,
+            HocoFrequency::Mhz20 => 20_000_000,
+        }
+    }
+}
+
 /// Configuration for the system clock dividers
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SystemClockDividerConfig {
@@ -146,6 +178,9 @@ impl Default for SystemClockDividerConfig {
 pub struct SystemClockConfig {
     pub system_clock_divider: SystemClockDividerConfig,
     pub external_oscillator: u32,
+    /// Must match the OFS1 option setting; used when HOCO is the system
+    /// clock or the PLL input.
+    pub hoco_frequency: HocoFrequency,
     pub clock_source: ClockSource,
 }
 
@@ -192,22 +227,68 @@ impl SystemClock {
         }
     }
 
-    pub fn get_system_clk_freq(&self) -> u32 {
-        // The chip resets to the MOCO clock source by default
-        let freq = match self.get_system_clock_src() {
-            ClockSource::HOCO => todo!(), 
-            ClockSource::MOCO => 8_000_000,   
-            ClockSource::LOCO => todo!(),       
-            ClockSource::MainClockOsc => self.config.external_oscillator,
-            ClockSource::SubClockOsc => todo!(), 
-            ClockSource::PLL => todo!(),    
+    /// PLL output frequency in Hz, from the PLLCCR input selection,
+    /// divider and multiplier. Panics on a reserved divider setting.
+    fn pll_freq(&self) -> u32 {
+        let (plidiv, plsrcsel, pllmul) = unsafe {
+            let pllccr = self.sysc.pllccr().read();
+            (pllccr.plidiv().get().0, pllccr.plsrcsel().get().0, pllccr.pllmul().get().0)
         };
+        let input = match plsrcsel {
+            0 => self.config.external_oscillator,
+            _ => self.config.hoco_frequency.hz(),
+        };
+        let divider = match plidiv {
+            0 => 1,
+            1 => 2,
+            2 => 3,
+            _ => panic!("Invalid PLL input divider"),
+        };
+        // PLLMUL selects x10.0 to x30.0 in steps of 0.5: (PLLMUL + 1) / 2
+        (input as u64 / divider * (pllmul as u64 + 1) / 2) as u32
+    }
+
+    /// Frequency of the selected clock source in Hz.
+    fn source_freq(&self) -> u32 {
+        match self.get_system_clock_src() {
+            ClockSource::HOCO => self.config.hoco_frequency.hz(),
+            ClockSource::MOCO => 8_000_000,
+            ClockSource::LOCO | ClockSource::SubClockOsc => 32_768,
+            ClockSource::MainClockOsc => self.config.external_oscillator,
+            ClockSource::PLL => self.pll_freq(),
+        }
+    }
+
+    /// Refreshes the cached ICLK frequency returned by `iclk_hz`.
+    fn update_iclk_cache(&self) {
+        ICLK_HZ.store(self.get_system_clk_freq(), Ordering::Relaxed);
+    }
+
+    /// Divides the selected clock source by `divider`.
+    fn divided_source_freq(&self, divider: ClockDividers) -> u32 {
+        let shift: u8 = divider.into();
+
+        self.source_freq() / (1 << shift) as u32
+    }
+
+    /// PCLKA frequency in Hz: the SCI, SPI and QSPI peripheral clock.
+    pub fn get_pclka_freq(&self) -> u32 {
+        self.divided_source_freq(self.get_clk_freq_divider().pcka)
+    }
 
-        let clk_div = self.get_clk_freq_divider();
-        let shift: u8 = clk_div.ick.into();
+    /// PCLKB frequency in Hz: the IIC, AGT and POEG peripheral clock.
+    pub fn get_pclkb_freq(&self) -> u32 {
+        self.divided_source_freq(self.get_clk_freq_divider().pckb)
+    }
 
-        freq / (1 << shift) as u32
-    }    
+    /// PCLKD frequency in Hz: the GPT count clock.
+    pub fn get_pclkd_freq(&self) -> u32 {
+        self.divided_source_freq(self.get_clk_freq_divider().pckd)
+    }
+
+    pub fn get_system_clk_freq(&self) -> u32 {
+        self.divided_source_freq(self.get_clk_freq_divider().ick)
+    }
 
     pub fn _enable_clock_write(&mut self) {
         // Enable write access to the clock control registers by setting PRC0 in the PRCR register
@@ -231,6 +312,49 @@ impl SystemClock {
         }
     }
 
+    pub fn _enable_low_power_write(&mut self) {
+        // Enable write access to the low power mode registers (SBYCR, ...) by
+        // setting PRC1 in the PRCR register
+        unsafe {
+            self.sysc.prcr().modify(|w| {
+                w.set_raw(0xA500 | 0x02)
+            });
+        }
+    }
+
+    pub fn _disable_low_power_write(&mut self) {
+        unsafe {
+            self.sysc.prcr().modify(|w| {
+                w.set_raw(0xA500 & !0x02)
+            });
+        }
+    }
+
+    /// Enters software standby and returns once a wakeup source enabled in the
+    /// ICU's WUPEN register (e.g. `icu::set_iic0_wakeup`) brings the chip back.
+    /// All clocks except the sub-clock and LOCO stop while in standby.
+    pub fn enter_software_standby(&mut self) {
+        cortex_m::interrupt::free(|_| {
+            unsafe {
+                self._enable_low_power_write();
+                self.sysc.sbycr().modify(|w| w.ssby().set(Ssby::_1));
+                let _ = self.sysc.sbycr().read();
+                self._disable_low_power_write();
+            }
+            // Interrupts stay masked by the critical section, but a pending
+            // wakeup still ends WFI; it is serviced when `free` returns.
+            cortex_m::asm::dsb();
+            cortex_m::asm::wfi();
+        });
+
+        // Leave SSBY clear so a plain WFI elsewhere only enters sleep mode
+        unsafe {
+            self._enable_low_power_write();
+            self.sysc.sbycr().modify(|w| w.ssby().set(Ssby::_0));
+            self._disable_low_power_write();
+        }
+    }
+
     pub fn set_system_clk_divder(&mut self, divider: ClockDividers) -> &mut Self {
         // Set the clock divider for the system clock, see PRCR register for write access
         // details.
@@ -247,6 +371,7 @@ impl SystemClock {
                 self._disable_clock_write();
             }
         });
+        self.update_iclk_cache();
         self
     }
 
@@ -265,6 +390,7 @@ impl SystemClock {
                 self._disable_clock_write();
             }
         });
+        self.update_iclk_cache();
         self
     }
 
@@ -0,0 +1,200 @@
+//! Three-phase complementary PWM for motor control, on three GPT channels.
+//!
+//! Each channel drives one half bridge: GTIOCnA the high-side gate, GTIOCnB
+//! the low-side gate. The channels run in triangle-wave mode from a common
+//! start, so all phases share one carrier. With automatic dead time
+//! (GTDTCR.TDE) the hardware derives GTCCRB from GTCCRA, so the low side
+//! switches off GTDVU before the high side switches on and back on GTDVD
+//! after it switches off. Duty cycles are buffered and take effect at the
+//! trough, or at both crest and trough.
+//!
+//! With `adc_trigger` set, each channel raises its A/D conversion request A
+//! (GTADTRA compare match) that many counts after the trough, while both low
+//! sides conduct; select it as the ADC's start trigger.
+
+use core::marker::PhantomData;
+
+use ra4m2_pac::{gpt320::{gtdtcr::Tde, gtintad::Adtrauen, gtior::{Oae, Obe}, gtuddtyc::{Ud, Udf}}, NoBitfieldReg, RegisterValue};
+
+use crate::{gpt::{self, ChannelSet, GtiocaPin, GtiocbPin, Instance, GTCCRA, GTCCRC}, sysc::SystemClock};
+
+/// When buffered duty cycles reach the compare registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferTransfer {
+    /// At the trough only: symmetric pulses (triangle-wave PWM mode 1).
+    Trough,
+    /// At crest and trough: the second half of a pulse can differ from the
+    /// first, halving the control latency (triangle-wave PWM mode 2).
+    CrestAndTrough,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreePhaseConfig {
+    /// Carrier frequency in Hz, rounded to the nearest rate the counter
+    /// allows.
+    pub frequency: u32,
+    /// Dead time in nanoseconds, rounded up to whole counts.
+    pub dead_time_ns: u32,
+    pub buffer_transfer: BufferTransfer,
+    /// Counts after the trough at which to request an A/D conversion, if
+    /// any.
+    pub adc_trigger: Option<u32>,
+}
+
+impl Default for ThreePhaseConfig {
+    fn default() -> Self {
+        ThreePhaseConfig {
+            frequency: 20_000,
+            dead_time_ns: 1_000,
+            buffer_transfer: BufferTransfer::Trough,
+            adc_trigger: None,
+        }
+    }
+}
+
+// GTIOR.GTIOA/GTIOB, as in `pwm`: the high side starts low and toggles at
+// each compare match, so it conducts around the crest; the low side is its
+// complement.
+const HIGH_SIDE: u8 = 0b0_11_00;
+const LOW_SIDE: u8 = 0b1_11_00;
+
+/// Three-phase PWM on channels `U`, `V` and `W`. Pins are borrowed for `'d`
+/// the same way as for `i2c::I2c`; the outputs sit low (both switches off)
+/// while stopped.
+pub struct ThreePhasePwm<'d, U: Instance, V: Instance, W: Instance> {
+    channels: (U, V, W),
+    /// The three channels, for the common start and stop.
+    set: ChannelSet,
+    /// GTPR: counts from trough to crest.
+    period: u32,
+    dead_time: u32,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, U: Instance, V: Instance, W: Instance> ThreePhasePwm<'d, U, V, W> {
+    /// Sets up all three channels, stopped, at 0 % duty. Panics if
+    /// `config.frequency` can't be reached from PCLKD on the smallest of the
+    /// three counters, or the dead time takes up the whole period.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<UH: GtiocaPin<U>, UL: GtiocbPin<U>, VH: GtiocaPin<V>, VL: GtiocbPin<V>, WH: GtiocaPin<W>, WL: GtiocbPin<W>>(
+        channels: (U, V, W),
+        _u: (&'d mut UH, &'d mut UL),
+        _v: (&'d mut VH, &'d mut VL),
+        _w: (&'d mut WH, &'d mut WL),
+        system_clock: &SystemClock,
+        config: ThreePhaseConfig,
+    ) -> Self {
+        assert!(
+            U::CHANNEL != V::CHANNEL && V::CHANNEL != W::CHANNEL && U::CHANNEL != W::CHANNEL,
+            "three-phase PWM needs three different channels"
+        );
+        cortex_m::interrupt::free(|cs| {
+            U::enable_power(cs);
+            V::enable_power(cs);
+            W::enable_power(cs);
+        });
+
+        let pclkd = system_clock.get_pclkd_freq();
+        let max = U::MAX_COUNT.min(V::MAX_COUNT).min(W::MAX_COUNT);
+        let (tpcs, period) = gpt::prescaler_setting(pclkd, max, |count_hz| {
+            let counts_per_cycle = 2 * config.frequency as u64;
+            // A frequency of 0 gives 0 counts, which no prescaler accepts
+            (count_hz as u64 + counts_per_cycle / 2).checked_div(counts_per_cycle).unwrap_or(0)
+        })
+        .expect("PWM frequency out of range for PCLKD");
+        let count_hz = pclkd / gpt::prescaler_divider(tpcs);
+        let dead_time = (config.dead_time_ns as u64 * count_hz as u64).div_ceil(1_000_000_000) as u32;
+        assert!(2 * dead_time < period, "dead time longer than the PWM period");
+
+        let md: u8 = match config.buffer_transfer {
+            BufferTransfer::Trough => 0b100,
+            BufferTransfer::CrestAndTrough => 0b101,
+        };
+
+        let mut pwm = ThreePhasePwm {
+            channels,
+            set: ChannelSet::new(),
+            period,
+            dead_time,
+            _pins: PhantomData,
+        };
+        let ccra = pwm.compare_value(0);
+        for regs in [U::regs(), V::regs(), W::regs()] {
+            unsafe {
+                regs.gtcr().modify(|w| w.set_raw(0));
+                regs.gtcr().modify(|w| w.md().set(md.into()).tpcs().set(tpcs.into()));
+                regs.gtuddtyc().modify(|w| w.set_raw(0).ud().set(Ud::_1).udf().set(Udf::_0));
+                // Single buffer operation for GTCCRA (from GTCCRC)
+                regs.gtber().modify(|w| w.set_raw(0).ccra().set(1.into()));
+                regs.gtpr().modify(|w| w.set(period));
+                regs.gtpbr().modify(|w| w.set(period));
+                regs.gtccr().get(GTCCRA).modify(|w| w.set(ccra));
+                regs.gtccr().get(GTCCRC).modify(|w| w.set(ccra));
+                regs.gtdvu().modify(|w| w.set(dead_time));
+                regs.gtdvd().modify(|w| w.set(dead_time));
+                regs.gtdtcr().modify(|w| w.set_raw(0).tde().set(Tde::_1));
+                if let Some(offset) = config.adc_trigger {
+                    regs.gtadtra().modify(|w| w.set(offset));
+                    regs.gtintad().modify(|w| w.adtrauen().set(Adtrauen::_1));
+                }
+                regs.gtcnt().modify(|w| w.set(0));
+                // Outputs low when stopped (OADFLT = OBDFLT = 0)
+                regs.gtior().modify(|w| {
+                    w.set_raw(0).gtioa().set(HIGH_SIDE.into()).oae().set(Oae::_1).gtiob().set(LOW_SIDE.into()).obe().set(Obe::_1)
+                });
+                // No hardware start, stop or clear sources
+                regs.gtssr().modify(|w| w.set_raw(0));
+                regs.gtpsr().modify(|w| w.set_raw(0));
+                regs.gtcsr().modify(|w| w.set_raw(0));
+            }
+        }
+
+        pwm.set = ChannelSet::new().with(&pwm.channels.0).with(&pwm.channels.1).with(&pwm.channels.2);
+        pwm
+    }
+
+    /// Stops the counters and releases the channels.
+    pub fn free(mut self) -> (U, V, W) {
+        self.stop();
+        for regs in [U::regs(), V::regs(), W::regs()] {
+            unsafe {
+                regs.gtior().modify(|w| w.set_raw(0));
+            }
+        }
+        self.channels
+    }
+
+    /// Starts all three counters in the same cycle.
+    pub fn start(&mut self) {
+        self.set.start();
+    }
+
+    /// Stops the counters; the outputs go low.
+    pub fn stop(&mut self) {
+        self.set.stop();
+    }
+
+    /// Full scale for `set_duty`: the period in counts, scaled down to fit a
+    /// `u16` for long periods.
+    pub fn max_duty(&self) -> u16 {
+        self.period.min(u16::MAX as u32) as u16
+    }
+
+    /// GTCCRA for a high-side duty of `duty` / `max_duty`, kept where both
+    /// dead times still fit in the period.
+    fn compare_value(&self, duty: u16) -> u32 {
+        let on = (duty as u64 * self.period as u64 / self.max_duty() as u64).min(self.period as u64) as u32;
+        (self.period - on).clamp(self.dead_time, self.period - self.dead_time)
+    }
+
+    /// Sets the high-side duty of phases U, V and W, from the next buffer
+    /// transfer. Duties near 0 % and 100 % are limited by the dead time.
+    pub fn set_duty(&mut self, duty: [u16; 3]) {
+        let [u, v, w] = duty.map(|duty| self.compare_value(duty));
+        unsafe {
+            U::regs().gtccr().get(GTCCRC).modify(|r| r.set(u));
+            V::regs().gtccr().get(GTCCRC).modify(|r| r.set(v));
+            W::regs().gtccr().get(GTCCRC).modify(|r| r.set(w));
+        }
+    }
+}
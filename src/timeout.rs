@@ -0,0 +1,48 @@
+//! Wall-clock deadlines for the drivers' busy-wait loops.
+//!
+//! Deadlines are measured with the DWT cycle counter and converted using the
+//! ICLK frequency cached by `SystemClock`, so a timeout lasts the same time
+//! whether the core runs from the 8 MHz MOCO or a 100 MHz PLL, and regardless
+//! of how tightly the compiler optimizes the polling loop.
+
+use embassy_time::Duration;
+
+use crate::sysc;
+
+/// Enables the DWT cycle counter if it isn't already running.
+fn enable_cycle_counter() {
+    // Only DEMCR.TRCENA and DWT_CTRL.CYCCNTENA are touched, both of which are
+    // idempotent, so stealing the core peripherals here is harmless.
+    let mut cp = unsafe { cortex_m::Peripherals::steal() };
+    cp.DCB.enable_trace();
+    cp.DWT.enable_cycle_counter();
+}
+
+/// A point in time, in core cycles, after which a wait should give up.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    start: u32,
+    cycles: u32,
+}
+
+impl Deadline {
+    /// Starts a deadline that expires `timeout` from now.
+    pub fn after(timeout: Duration) -> Self {
+        enable_cycle_counter();
+
+        // CYCCNT is 32 bits wide, so saturate rather than wrap. At 100 MHz
+        // that still allows timeouts of ~42 s.
+        let cycles = timeout.as_micros() * sysc::iclk_hz() as u64 / 1_000_000;
+        let cycles = cycles.min(u32::MAX as u64) as u32;
+
+        Deadline {
+            start: cortex_m::peripheral::DWT::cycle_count(),
+            cycles,
+        }
+    }
+
+    /// Returns `true` once the timeout has elapsed.
+    pub fn expired(&self) -> bool {
+        cortex_m::peripheral::DWT::cycle_count().wrapping_sub(self.start) >= self.cycles
+    }
+}
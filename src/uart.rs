@@ -0,0 +1,661 @@
+//! Blocking asynchronous-mode UART on the SCI channels.
+//!
+//! The bit rate is derived from PCLKA. `baud_setting` searches the clock
+//! select (CKS), base clock (ABCS / BGDM) and, when that alone can't get
+//! close enough, the modulation duty register (MDDR) for the setting with the
+//! smallest bit rate error.
+//!
+//! Hardware flow control uses the channel's CTSn_RTSn pin as either CTS input
+//! or RTS output (SPMR.CTSE); the RA4M2 SCI has one pin for both. `Rs485`
+//! drives a transceiver's driver-enable line around each transmission. The
+//! SCI here has no DE output, so DE is any push-pull GPIO. `Multiprocessor`
+//! switches to 9-bit multiprocessor framing (SMR.MP), where the extra bit
+//! marks address frames.
+
+use core::{convert::Infallible, marker::PhantomData};
+
+use embassy_time::Duration;
+
+use ra4m2_pac::{sci0::{scmr::{Chr1, Sdir, Sinv, Smif}, scr::{Mpie, Re, Te}, semr::{Abcs, Bgdm, Brme, Rxdesel}, simr1::Iicm, spmr::Ctse, smr::{Chr, Cm, Mp, Pe, Pm, Stop}, ssr::{Fer, Mpbt, Orer, Per}}, RegisterValue};
+
+use crate::{sci::{CtsRtsPin, Instance, RxdPin, TxdPin}, sysc::{self, SystemClock}, timeout::Deadline};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Seven,
+    Eight,
+}
+
+/// Use of the CTSn_RTSn pin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    /// CTS input: transmission pauses while the pin is high.
+    Cts,
+    /// RTS output: driven low while the receiver can take another byte.
+    Rts,
+}
+
+/// UART configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartConfig {
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub data_bits: DataBits,
+}
+
+impl Default for UartConfig {
+    fn default() -> Self {
+        UartConfig {
+            baud_rate: 115_200,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            data_bits: DataBits::Eight,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UartError {
+    /// A stop bit was read as 0.
+    Framing,
+    /// The received parity bit didn't match.
+    Parity,
+    /// A byte arrived before the previous one was read.
+    Overrun,
+    /// A byte couldn't be queued, or transmission didn't end, within
+    /// `Rs485Config::timeout`.
+    Timeout,
+}
+
+impl embedded_io::Error for UartError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match *self {
+            UartError::Framing => embedded_io::ErrorKind::InvalidData,
+            UartError::Parity => embedded_io::ErrorKind::InvalidData,
+            UartError::Overrun => embedded_io::ErrorKind::Other,
+            UartError::Timeout => embedded_io::ErrorKind::TimedOut,
+        }
+    }
+}
+
+impl embedded_hal_nb::serial::Error for UartError {
+    fn kind(&self) -> embedded_hal_nb::serial::ErrorKind {
+        match *self {
+            UartError::Framing => embedded_hal_nb::serial::ErrorKind::FrameFormat,
+            UartError::Parity => embedded_hal_nb::serial::ErrorKind::Parity,
+            UartError::Overrun => embedded_hal_nb::serial::ErrorKind::Overrun,
+            UartError::Timeout => embedded_hal_nb::serial::ErrorKind::Other,
+        }
+    }
+}
+
+/// Register values producing a bit rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BaudSetting {
+    pub cks: u8,
+    pub brr: u8,
+    pub bgdm: bool,
+    pub abcs: bool,
+    /// MDDR value when bit rate modulation is needed.
+    pub mddr: Option<u8>,
+}
+
+/// Finds the asynchronous mode setting closest to `baud_rate`:
+///
+///   B = PCLK / (64 * 2^(2n-1) * (N + 1)) * M / 256
+///
+/// where the 64 becomes 32 with BGDM or ABCS set and 16 with both.
+pub(crate) fn baud_setting(pclk: u32, baud_rate: u32) -> Option<BaudSetting> {
+    if baud_rate == 0 {
+        return None;
+    }
+
+    let mut best: Option<(u64, BaudSetting)> = None;
+    let mut consider = |base: u64, k: u64, m: u64, setting: BaudSetting| {
+        // Error in parts per million
+        let actual = pclk as u64 * m * 1_000_000 / (256 * base * k);
+        let target = baud_rate as u64 * 1_000_000;
+        let error = actual.abs_diff(target) / baud_rate as u64;
+        if best.is_none_or(|(best_error, _)| error < best_error) {
+            best = Some((error, setting));
+        }
+    };
+
+    for (base, bgdm, abcs) in [(32u64, false, false), (16, true, false), (8, true, true)] {
+        for cks in 0..4u8 {
+            let base = base * (1 << (2 * cks));
+            let divisor = base * baud_rate as u64;
+
+            // Plain setting, N + 1 rounded to nearest
+            let k = (pclk as u64 + divisor / 2) / divisor;
+            if (1..=256).contains(&k) {
+                consider(base, k, 256, BaudSetting { cks, brr: (k - 1) as u8, bgdm, abcs, mddr: None });
+            }
+
+            // Modulated: round N + 1 down, then stretch with M / 256
+            let k = pclk as u64 / divisor;
+            if (1..=256).contains(&k) {
+                let m = (256 * k * divisor + pclk as u64 / 2) / pclk as u64;
+                if (128..256).contains(&m) {
+                    consider(base, k, m, BaudSetting { cks, brr: (k - 1) as u8, bgdm, abcs, mddr: Some(m as u8) });
+                }
+            }
+        }
+    }
+
+    best.map(|(_, setting)| setting)
+}
+
+/// Blocking UART driver, generic over the SCI channel. Pins are borrowed for
+/// `'d` the same way as for `i2c::I2c`; mux TX with
+/// `into_alternate_function_with(.., DrainControl::PushPull, ..)`.
+pub struct Uart<'d, T: Instance> {
+    sci: T,
+    _pins: PhantomData<&'d mut ()>,
+}
+
+impl<'d, T: Instance> Uart<'d, T> {
+    /// Creates the UART and enables its transmitter and receiver. Panics if
+    /// the baud rate can't be generated from PCLKA.
+    pub fn new<TX: TxdPin<T>, RX: RxdPin<T>>(
+        sci: T,
+        _tx: &'d mut TX,
+        _rx: &'d mut RX,
+        system_clock: &SystemClock,
+        config: UartConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        let baud = baud_setting(system_clock.get_pclka_freq(), config.baud_rate)
+            .expect("Baud rate out of range for PCLKA");
+
+        let mut uart = Uart { sci, _pins: PhantomData };
+        uart.configure(&config, &baud, None);
+        uart
+    }
+
+    /// Like `new`, with hardware flow control on the channel's CTSn_RTSn
+    /// pin, muxed to `T::PIN_FUNCTION`.
+    pub fn new_with_flow_control<TX: TxdPin<T>, RX: RxdPin<T>, FC: CtsRtsPin<T>>(
+        sci: T,
+        _tx: &'d mut TX,
+        _rx: &'d mut RX,
+        _cts_rts: &'d mut FC,
+        flow_control: FlowControl,
+        system_clock: &SystemClock,
+        config: UartConfig,
+    ) -> Self {
+        cortex_m::interrupt::free(|cs| {
+            T::enable_power(cs);
+        });
+
+        let baud = baud_setting(system_clock.get_pclka_freq(), config.baud_rate)
+            .expect("Baud rate out of range for PCLKA");
+
+        let mut uart = Uart { sci, _pins: PhantomData };
+        uart.configure(&config, &baud, Some(flow_control));
+        uart
+    }
+
+    /// Releases the channel.
+    pub fn free(self) -> T {
+        self.sci
+    }
+
+    /// Writes the asynchronous mode settings; see the SCI initialization
+    /// flowchart in the RA4M2 manual.
+    fn configure(&mut self, config: &UartConfig, baud: &BaudSetting, flow_control: Option<FlowControl>) {
+        unsafe {
+            let regs = T::regs();
+            regs.scr().modify(|w| w.set_raw(0));
+
+            regs.simr1().modify(|w| w.iicm().set(Iicm::_0));
+            // CTSE = 0 leaves the CTSn_RTSn pin as RTS output when it is muxed
+            let ctse = if flow_control == Some(FlowControl::Cts) { Ctse::_1 } else { Ctse::_0 };
+            regs.spmr().modify(|w| w.set_raw(0).ctse().set(ctse));
+
+            let (pe, pm) = match config.parity {
+                Parity::None => (Pe::_0, Pm::_0),
+                Parity::Even => (Pe::_1, Pm::_0),
+                Parity::Odd => (Pe::_1, Pm::_1),
+            };
+            let chr = match config.data_bits {
+                DataBits::Seven => Chr::_1,
+                DataBits::Eight => Chr::_0,
+            };
+            let stop = match config.stop_bits {
+                StopBits::One => Stop::_0,
+                StopBits::Two => Stop::_1,
+            };
+            regs.smr().modify(|w| {
+                w.cm().set(Cm::_0).chr().set(chr).pe().set(pe).pm().set(pm).stop().set(stop)
+                    .mp().set(Mp::_0).cks().set(baud.cks.into())
+            });
+            regs.scmr().modify(|w| {
+                w.smif().set(Smif::_0).sinv().set(Sinv::_0).sdir().set(Sdir::_0).chr1().set(Chr1::_1)
+            });
+
+            regs.semr().modify(|w| {
+                w.bgdm().set(if baud.bgdm { Bgdm::_1 } else { Bgdm::_0 })
+                    .abcs().set(if baud.abcs { Abcs::_1 } else { Abcs::_0 })
+                    .brme().set(if baud.mddr.is_some() { Brme::_1 } else { Brme::_0 })
+                    .rxdesel().set(Rxdesel::_1)
+            });
+            regs.brr().modify(|w| w.set_raw(baud.brr));
+            if let Some(mddr) = baud.mddr {
+                regs.mddr().modify(|w| w.set_raw(mddr));
+            }
+
+            // Wait at least one bit period before enabling, per the manual.
+            // TE and RE must be set in a single write.
+            cortex_m::asm::delay(sysc::iclk_hz() / config.baud_rate + 1);
+            regs.scr().modify(|w| w.te().set(Te::_1).re().set(Re::_1));
+        }
+    }
+
+    /// Checks and clears the receive error flags.
+    fn check_errors(&mut self) -> Result<(), UartError> {
+        unsafe {
+            let ssr = T::regs().ssr().read();
+            let error = if ssr.orer().get().0 == 1 {
+                Some(UartError::Overrun)
+            } else if ssr.fer().get().0 == 1 {
+                Some(UartError::Framing)
+            } else if ssr.per().get().0 == 1 {
+                Some(UartError::Parity)
+            } else {
+                None
+            };
+
+            if let Some(error) = error {
+                T::regs().ssr().modify(|w| w.orer().set(Orer::_0).fer().set(Fer::_0).per().set(Per::_0));
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_data_received(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().rdrf().get().0 == 1
+        }
+    }
+
+    fn transmit_buffer_ready(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().tdre().get().0 == 1
+        }
+    }
+
+    fn transmit_complete(&self) -> bool {
+        unsafe {
+            T::regs().ssr().read().tend().get().0 == 1
+        }
+    }
+
+    /// Returns a received byte, `WouldBlock` if none is waiting.
+    pub fn read_byte(&mut self) -> nb::Result<u8, UartError> {
+        self.check_errors()?;
+        if !self.is_data_received() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(unsafe { T::regs().rdr().read().get_raw() })
+    }
+
+    /// Queues a byte for transmission, `WouldBlock` if the buffer is full.
+    pub fn write_byte(&mut self, byte: u8) -> nb::Result<(), UartError> {
+        if !self.transmit_buffer_ready() {
+            return Err(nb::Error::WouldBlock);
+        }
+        unsafe {
+            T::regs().tdr().modify(|w| w.set_raw(byte));
+        }
+        Ok(())
+    }
+
+    /// `WouldBlock` until the last byte has left the shift register.
+    pub fn flush_tx(&mut self) -> nb::Result<(), UartError> {
+        if self.transmit_complete() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<'d, T: Instance> embedded_hal_nb::serial::ErrorType for Uart<'d, T> {
+    type Error = UartError;
+}
+
+impl<'d, T: Instance> embedded_hal_nb::serial::Read<u8> for Uart<'d, T> {
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.read_byte()
+    }
+}
+
+impl<'d, T: Instance> embedded_hal_nb::serial::Write<u8> for Uart<'d, T> {
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.write_byte(word)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.flush_tx()
+    }
+}
+
+impl<'d, T: Instance> embedded_io::ErrorType for Uart<'d, T> {
+    type Error = UartError;
+}
+
+impl<'d, T: Instance> embedded_io::Read for Uart<'d, T> {
+    /// Blocks until at least one byte is available, then returns whatever has
+    /// arrived without blocking again.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        buf[0] = nb::block!(self.read_byte())?;
+        let mut count = 1;
+        while count < buf.len() {
+            match self.read_byte() {
+                Ok(byte) => {
+                    buf[count] = byte;
+                    count += 1;
+                }
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(error)) => return Err(error),
+            }
+        }
+        Ok(count)
+    }
+}
+
+impl<'d, T: Instance> embedded_io::Write for Uart<'d, T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        for byte in buf {
+            nb::block!(self.write_byte(*byte))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        nb::block!(self.flush_tx())
+    }
+}
+
+/// RS-485 driver-enable timing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rs485Config {
+    /// Time between asserting DE and the start bit of the first byte.
+    pub assert_time: Duration,
+    /// Time between transmit end (the stop bit of the last byte) and
+    /// releasing DE.
+    pub deassert_time: Duration,
+    /// How long each byte may wait for the transmit buffer, and the last one
+    /// for transmit end, before `write` gives up with `UartError::Timeout`
+    /// and releases DE. Must cover a whole frame at the bit rate in use,
+    /// plus any time CTS holds transmission back.
+    pub timeout: Duration,
+}
+
+impl Default for Rs485Config {
+    fn default() -> Self {
+        Rs485Config {
+            assert_time: Duration::from_micros(0),
+            deassert_time: Duration::from_micros(0),
+            timeout: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Half-duplex RS-485 on top of `Uart`: each `write` asserts DE, sends the
+/// data, waits for transmit end (the TEI condition, SSR.TEND) and releases
+/// DE again, so the bus is free as soon as `write` returns. DE is released
+/// even if a wait times out.
+pub struct Rs485<'d, T: Instance, DE: embedded_hal::digital::OutputPin<Error = Infallible>> {
+    uart: Uart<'d, T>,
+    de: &'d mut DE,
+    config: Rs485Config,
+}
+
+impl<'d, T: Instance, DE: embedded_hal::digital::OutputPin<Error = Infallible>> Rs485<'d, T, DE> {
+    /// Takes over `uart` with DE on `de`, which is released immediately.
+    pub fn new(uart: Uart<'d, T>, de: &'d mut DE, config: Rs485Config) -> Self {
+        let _ = de.set_low();
+        Rs485 { uart, de, config }
+    }
+
+    /// Releases the UART.
+    pub fn free(self) -> Uart<'d, T> {
+        self.uart
+    }
+
+    fn wait(duration: Duration) {
+        let deadline = Deadline::after(duration);
+        while !deadline.expired() {}
+    }
+
+    /// Retries `operation` until it stops blocking, failing with
+    /// `UartError::Timeout` once the configured timeout has elapsed.
+    fn wait_for(&mut self, mut operation: impl FnMut(&mut Uart<'d, T>) -> nb::Result<(), UartError>) -> Result<(), UartError> {
+        let deadline = Deadline::after(self.config.timeout);
+        loop {
+            match operation(&mut self.uart) {
+                Ok(()) => return Ok(()),
+                Err(nb::Error::Other(error)) => return Err(error),
+                Err(nb::Error::WouldBlock) if deadline.expired() => return Err(UartError::Timeout),
+                Err(nb::Error::WouldBlock) => {}
+            }
+        }
+    }
+
+    fn transmit(&mut self, buf: &[u8]) -> Result<(), UartError> {
+        let _ = self.de.set_high();
+        Self::wait(self.config.assert_time);
+
+        let result = buf.iter().try_for_each(|byte| self.wait_for(|uart| uart.write_byte(*byte)));
+        let flushed = self.wait_for(Uart::flush_tx);
+
+        Self::wait(self.config.deassert_time);
+        let _ = self.de.set_low();
+        result.and(flushed)
+    }
+}
+
+impl<'d, T: Instance, DE: embedded_hal::digital::OutputPin<Error = Infallible>> embedded_io::ErrorType for Rs485<'d, T, DE> {
+    type Error = UartError;
+}
+
+impl<'d, T: Instance, DE: embedded_hal::digital::OutputPin<Error = Infallible>> embedded_io::Read for Rs485<'d, T, DE> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        embedded_io::Read::read(&mut self.uart, buf)
+    }
+}
+
+impl<'d, T: Instance, DE: embedded_hal::digital::OutputPin<Error = Infallible>> embedded_io::Write for Rs485<'d, T, DE> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.transmit(buf)?;
+        Ok(buf.len())
+    }
+
+    /// `write` already waits for transmit end.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A frame received in multiprocessor mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    /// Multiprocessor bit set: a station address.
+    Address(u8),
+    Data(u8),
+}
+
+/// Multiprocessor (9-bit address) communication on top of `Uart`. Data frames
+/// are filtered in hardware (SCR.MPIE) until an address frame arrives; frames
+/// for other stations are skipped by re-arming the filter.
+pub struct Multiprocessor<'d, T: Instance> {
+    uart: Uart<'d, T>,
+    station: u8,
+    /// This station was addressed and no other address frame has arrived
+    /// since.
+    selected: bool,
+    /// An address frame that ended the previous `receive`.
+    pending_address: Option<u8>,
+    /// SMR.PE and SMR.PM of the plain format, restored by `free`.
+    parity: (Pe, Pm),
+}
+
+impl<'d, T: Instance> Multiprocessor<'d, T> {
+    /// Switches `uart` to multiprocessor framing, listening for `station`.
+    /// Parity is not used in this format and is restored by `free`; the
+    /// configured data and stop bits are kept.
+    pub fn new(uart: Uart<'d, T>, station: u8) -> Self {
+        let parity = unsafe {
+            let regs = T::regs();
+            let smr = regs.smr().read();
+            let parity = (smr.pe().get(), smr.pm().get());
+            // SMR can only be written with TE and RE cleared
+            regs.scr().modify(|w| w.te().set(Te::_0).re().set(Re::_0));
+            regs.smr().modify(|w| w.mp().set(Mp::_1).pe().set(Pe::_0));
+            regs.scr().modify(|w| w.te().set(Te::_1).re().set(Re::_1));
+            parity
+        };
+
+        let mut multiprocessor = Multiprocessor { uart, station, selected: false, pending_address: None, parity };
+        multiprocessor.listen();
+        multiprocessor
+    }
+
+    /// Releases the UART, back in the plain asynchronous format with its
+    /// parity setting.
+    pub fn free(self) -> Uart<'d, T> {
+        let (pe, pm) = self.parity;
+        unsafe {
+            let regs = T::regs();
+            regs.scr().modify(|w| w.te().set(Te::_0).re().set(Re::_0).mpie().set(Mpie::_0));
+            regs.smr().modify(|w| w.mp().set(Mp::_0).pe().set(pe).pm().set(pm));
+            regs.scr().modify(|w| w.te().set(Te::_1).re().set(Re::_1));
+        }
+        self.uart
+    }
+
+    /// Changes the station address this node answers to. Data for the old
+    /// address is discarded until the next address frame.
+    pub fn set_station(&mut self, station: u8) {
+        self.station = station;
+        self.listen();
+    }
+
+    /// Discards data frames in hardware until the next address frame.
+    pub fn listen(&mut self) {
+        self.selected = false;
+        unsafe {
+            T::regs().scr().modify(|w| w.mpie().set(Mpie::_1));
+        }
+    }
+
+    fn write_frame(&mut self, byte: u8, address: bool) -> nb::Result<(), UartError> {
+        if !self.uart.transmit_buffer_ready() {
+            return Err(nb::Error::WouldBlock);
+        }
+        unsafe {
+            T::regs().ssr().modify(|w| w.mpbt().set(if address { Mpbt::_1 } else { Mpbt::_0 }));
+            T::regs().tdr().modify(|w| w.set_raw(byte));
+        }
+        Ok(())
+    }
+
+    /// Sends an address frame, selecting `station` on the bus.
+    pub fn send_address(&mut self, station: u8) -> Result<(), UartError> {
+        nb::block!(self.write_frame(station, true))
+    }
+
+    /// Sends data frames to the station last addressed.
+    pub fn send_data(&mut self, data: &[u8]) -> Result<(), UartError> {
+        for byte in data {
+            nb::block!(self.write_frame(*byte, false))?;
+        }
+        Ok(())
+    }
+
+    /// Waits until the last frame has left the shift register.
+    pub fn flush(&mut self) -> Result<(), UartError> {
+        nb::block!(self.uart.flush_tx())
+    }
+
+    /// Returns the next frame that passed the hardware filter, `WouldBlock`
+    /// if none is waiting.
+    pub fn read_frame(&mut self) -> nb::Result<Frame, UartError> {
+        self.uart.check_errors()?;
+        if !self.uart.is_data_received() {
+            return Err(nb::Error::WouldBlock);
+        }
+        unsafe {
+            // MPB belongs to the byte in RDR, so read it first
+            let address = T::regs().ssr().read().mpb().get().0 == 1;
+            let byte = T::regs().rdr().read().get_raw();
+            Ok(if address { Frame::Address(byte) } else { Frame::Data(byte) })
+        }
+    }
+
+    /// Blocks until this station is addressed, then receives its data frames
+    /// into `buffer` until the buffer is full or the next address frame
+    /// arrives. Returns the number of data bytes. If the buffer filled, the
+    /// next call carries on with the rest of the same message.
+    pub fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, UartError> {
+        while !self.selected {
+            let address = match self.pending_address.take() {
+                Some(address) => address,
+                None => match nb::block!(self.read_frame())? {
+                    Frame::Address(address) => address,
+                    // Data that passed the filter before this station was
+                    // ever addressed
+                    Frame::Data(_) => continue,
+                },
+            };
+            if address == self.station {
+                self.selected = true;
+            } else {
+                self.listen();
+            }
+        }
+
+        let mut count = 0;
+        while count < buffer.len() {
+            match nb::block!(self.read_frame())? {
+                Frame::Data(byte) => {
+                    buffer[count] = byte;
+                    count += 1;
+                }
+                Frame::Address(address) => {
+                    self.selected = false;
+                    self.pending_address = Some(address);
+                    return Ok(count);
+                }
+            }
+        }
+        Ok(count)
+    }
+}
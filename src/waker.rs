@@ -0,0 +1,35 @@
+//! Waker storage shared between a driver's futures and its interrupt handler.
+
+use core::{cell::RefCell, task::Waker};
+
+/// Holds the waker of the task currently waiting on a peripheral event.
+pub(crate) struct WakerSlot {
+    waker: cortex_m::interrupt::Mutex<RefCell<Option<Waker>>>,
+}
+
+impl WakerSlot {
+    pub const fn new() -> Self {
+        WakerSlot {
+            waker: cortex_m::interrupt::Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Stores `waker`, replacing (without waking) any previous one.
+    pub fn register(&self, waker: &Waker) {
+        cortex_m::interrupt::free(|cs| {
+            let mut slot = self.waker.borrow(cs).borrow_mut();
+            match slot.as_ref() {
+                Some(current) if current.will_wake(waker) => {}
+                _ => *slot = Some(waker.clone()),
+            }
+        });
+    }
+
+    /// Wakes and clears the stored waker, if any. Safe to call from interrupts.
+    pub fn wake(&self) {
+        let waker = cortex_m::interrupt::free(|cs| self.waker.borrow(cs).borrow_mut().take());
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}